@@ -0,0 +1,9 @@
+// arduino-sdk-sys can not pass link args to the firmware itself, cargo gives them to the
+// build script of its direct dependents as DEP_ARDUINO_SDK_LINK_ARGS, separated by \x1f
+fn main() {
+    println!("cargo:rerun-if-env-changed=DEP_ARDUINO_SDK_LINK_ARGS");
+    let args = std::env::var("DEP_ARDUINO_SDK_LINK_ARGS").unwrap_or_default();
+    for a in args.split('\x1f').filter(|a| !a.is_empty()) {
+        println!("cargo:rustc-link-arg={}", a);
+    }
+}
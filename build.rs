@@ -1,14 +1,23 @@
-
 #[cfg(feature = "prettify_bindgen")]
 extern crate clang;
 
 
 use std::{env, };
 
-include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/build_util_for_arduino.rs"));
+// the build helpers keep their `== false` / `len() > 0` style
+#[allow(
+    clippy::bool_comparison,
+    clippy::len_zero,
+    clippy::needless_lifetimes,
+    clippy::needless_return,
+    clippy::let_and_return
+)]
+mod build_util {
+    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/build_util_for_arduino.rs"));
+}
 
 fn main() {
-    main_entry();
-    return;
+    // the host triple of the `arduino-sdk` tool, see src/bin/arduino-sdk.rs
+    println!("cargo:rustc-env=ARDUINO_SDK_HOST={}", env::var("HOST").unwrap());
+    build_util::main_entry();
 }
-
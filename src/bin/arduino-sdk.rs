@@ -0,0 +1,1482 @@
+//! `arduino-sdk`, the cache and inspection tasks of the build script as a command line tool.
+//! build it with `cargo install arduino-sdk-sys --features cli`.
+
+// the tool keeps the `== false` / `len() > 0` style of the build helpers
+#![allow(
+    clippy::bool_comparison,
+    clippy::len_zero,
+    clippy::needless_lifetimes,
+    clippy::needless_return,
+    clippy::let_and_return
+)]
+
+// the build helpers are shared with build.rs, the tool does not use all of them
+#[allow(dead_code)]
+mod build_util {
+    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/build_util_for_arduino.rs"));
+}
+use build_util::{arduino_cli_util, compile_bindgen, core_bundle, object_cache};
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+const USAGE: &str = "usage:
+    arduino-sdk prebuild <fqbn> [--target <rust target>] [--opt-level <level>] [--debug]
+        compile libarduino_core.a of <fqbn> into the shared cache. the cache entry is only used
+        by builds with the same target, opt-level (default s) and debug setting
+    arduino-sdk export <fqbn> <bundle.tar.gz> [--bindings <dir>] [--target ..] [--opt-level ..] [--debug]
+        pack the cached libarduino_core.a of <fqbn> with its headers and the bindings of <dir>
+    arduino-sdk import <fqbn> <bundle.tar.gz> [--target ..] [--opt-level ..] [--debug]
+        put the core of a bundle into the shared cache, if it fits the installed platform
+    arduino-sdk objcopy [<config yaml>] <elf>
+        write the .hex/.eep/.bin of the platform objcopy recipes next to <elf>, the config
+        defaults to ARDUINO_SDK_CONFIG, so it works as a cargo runner
+    arduino-sdk size [<config yaml>] <elf> [--flash-percent <n>] [--ram-percent <n>]
+        print the flash and RAM use of <elf> like the arduino IDE, fail if it does not fit the
+        board or is over the budget in percent of the board maximum
+    arduino-sdk memory [<config yaml>] <elf> [--report <json>] [--top <n>] [--nm <program>]
+        flash and RAM of <elf> per component: the core, each library archive, rust and other.
+        the archives come from the arduino_build_report.json of the build (found below the
+        target dir of <elf> by default)
+    arduino-sdk upload [<config yaml>] <elf|hex> [--port <port>] [--verbose] [--verify] [--print]
+        flash the firmware by tools.<upload.tool>.upload.pattern, the port defaults to ARDUINO_SDK_PORT.
+        an elf is converted by the objcopy recipes first. --print only prints the command
+    arduino-sdk postbuild [<config yaml>] <elf> [--dry-run]
+        run the allowed platform hooks after linking (linking.postlink, objcopy, savehex,
+        postbuild) and the objcopy recipes. {build.path} is the dir of <elf> and
+        {build.project_name} its name
+    arduino-sdk target-spec [<config yaml>] [--out <file|dir>] [--linker <program>] [--check <target>]
+        print or write the rust target spec of an avr board, from build.mcu, build.f_cpu and the
+        linker of recipe.c.combine.pattern. --check fails if <target> (a spec file or a target
+        name) is for another MCU than the board
+    arduino-sdk show <config yaml>
+        print the resolved board, recipes and libraries of a down-stream config
+    arduino-sdk clean [--cache-dir <dir>]
+        remove every cached core archive and object
+    arduino-sdk gc [--cache-dir <dir>] [--days <n>]
+        remove the cores of uninstalled platform versions, and the objects that are stale or
+        were not used for <n> days (30 by default)
+    arduino-sdk doctor [<config yaml>]
+        check arduino-cli, the installed platforms, the recipe compilers and libclang";
+
+/// the recipes `show` prints, in build order
+const RECIPES: [&str; 5] = [
+    "recipe.c.o.pattern",
+    "recipe.cpp.o.pattern",
+    "recipe.S.o.pattern",
+    "recipe.ar.pattern",
+    "recipe.c.combine.pattern",
+];
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let rest = args.get(1..).unwrap_or_default();
+    let result = match args.first().map(String::as_str) {
+        Some("prebuild") => prebuild(rest),
+        Some("export") => export(rest),
+        Some("import") => import(rest),
+        Some("objcopy") => objcopy(rest),
+        Some("size") => size(rest),
+        Some("memory") => memory(rest),
+        Some("upload") => upload(rest),
+        Some("postbuild") => postbuild(rest),
+        Some("target-spec") => target_spec(rest),
+        Some("show") => show(rest),
+        Some("clean") => clean(rest),
+        Some("gc") => gc(rest),
+        Some("doctor") => doctor(rest),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// split `args` into the `--name value` options listed in `options`, the `--name` switches listed
+/// in `switches` (with an empty value) and the positional arguments
+fn parse_args(
+    args: &[String],
+    options: &[&str],
+    switches: &[&str],
+) -> Result<(HashMap<String, String>, Vec<String>), String> {
+    let mut named = HashMap::new();
+    let mut positional = vec![];
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        if switches.contains(&a.as_str()) {
+            named.insert(a.to_owned(), String::new());
+        } else if a.starts_with("--") {
+            if options.contains(&a.as_str()) == false {
+                return Err(format!("unknown option '{}'\n\n{}", a, USAGE));
+            }
+            let value = it.next().ok_or(format!("'{}' needs a value", a))?;
+            named.insert(a.to_owned(), value.to_owned());
+        } else {
+            positional.push(a.to_owned());
+        }
+    }
+    Ok((named, positional))
+}
+
+fn load_info(config: &Path) -> Result<arduino_cli_util::Info, String> {
+    if config.is_file() == false {
+        return Err(format!("{}: no such file", config.display()));
+    }
+    let downstream_config = arduino_cli_util::DownStreamConfig::new(config.to_str());
+    if downstream_config.get_fqbn().is_none() {
+        return Err(format!("{}: no fqbn", config.display()));
+    }
+    arduino_cli_util::Info::from_config(downstream_config).ok_or(format!(
+        "{}: arduino-cli can not resolve the board, see `arduino-sdk doctor`",
+        config.display()
+    ))
+}
+
+/// the [`arduino_cli_util::Info`] of a config with only `fqbn` and `shared_core_cache: true`,
+/// written to `dir`
+fn fqbn_info(fqbn: &str, dir: &Path) -> Result<arduino_cli_util::Info, String> {
+    let config = dir.join("arduino-sdk.yaml");
+    std::fs::write(&config, format!("fqbn: \"{}\"\nshared_core_cache: true\n", fqbn))
+        .map_err(|e| e.to_string())?;
+    load_info(&config)
+}
+
+/// `<dir>/arduino-lib`, the dir holds the cached cores and `objects/`.
+/// the cache root is `--cache-dir`, `ARDUINO_SDK_CACHE_DIR` or the per-user cache dir.
+fn cache_lib_dir(options: &HashMap<String, String>) -> Result<PathBuf, String> {
+    let root = if let Some(d) = options.get("--cache-dir") {
+        PathBuf::from(d)
+    } else if let Ok(d) = std::env::var("ARDUINO_SDK_CACHE_DIR") {
+        PathBuf::from(d)
+    } else {
+        arduino_cli_util::user_cache_dir().ok_or("no user cache dir, pass --cache-dir")?
+    };
+    Ok(root.join("arduino-lib"))
+}
+
+/// the rust target of the usual boards of `arch`
+fn default_target(arch: &str) -> Option<&'static str> {
+    match arch {
+        "avr" => Some("avr-none"),
+        "samd" => Some("thumbv6m-none-eabi"),
+        "sam" => Some("thumbv7m-none-eabi"),
+        "esp32" => Some("xtensa-esp32-none-elf"),
+        "esp8266" => Some("xtensa-esp8266-none-elf"),
+        _ => None,
+    }
+}
+
+/// a standalone factory for the core of `info` by `--target` (default by the arch), `--opt-level`
+/// (default `s`) and `--debug`. they pick the cache entry, see `CompileFactory::core_archive_dir`.
+fn core_factory<'a>(
+    info: &'a arduino_cli_util::Info,
+    options: &HashMap<String, String>,
+    out_dir: &Path,
+) -> Result<compile_bindgen::CompileFactory<'a>, String> {
+    let arch = info.get_arch();
+    let target = match options.get("--target") {
+        Some(t) => t.to_owned(),
+        None => default_target(&arch)
+            .ok_or(format!("no default rust target for '{}', pass --target", arch))?
+            .to_string(),
+    };
+    Ok(compile_bindgen::CompileFactory::standalone(
+        info,
+        compile_bindgen::Standalone {
+            out_dir: out_dir.to_owned(),
+            target,
+            host: env!("ARDUINO_SDK_HOST").to_string(),
+            opt_level: options.get("--opt-level").cloned().unwrap_or("s".to_string()),
+            debug: options.contains_key("--debug"),
+        },
+    ))
+}
+
+fn prebuild(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--target", "--opt-level"], &["--debug"])?;
+    let [fqbn] = positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let tmp = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let info = fqbn_info(fqbn, tmp.path())?;
+    let factory = core_factory(&info, &options, &tmp.path().join("out"))?;
+    let dir = factory.core_archive_dir();
+    if factory.compile_core(&dir).0 != dir {
+        return Err(format!("the core could not be cached in {}", dir.display()));
+    }
+    println!("{}", dir.join(format!("lib{}.a", compile_bindgen::CORE_NAME)).display());
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--bindings", "--target", "--opt-level"], &["--debug"])?;
+    let [fqbn, bundle] = positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let tmp = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let info = fqbn_info(fqbn, tmp.path())?;
+    let factory = core_factory(&info, &options, &tmp.path().join("out"))?;
+    let bindings = options.get("--bindings").map(Path::new);
+    let (digest, dir) = (factory.core_digest(), factory.core_archive_dir());
+    let manifest = core_bundle::export(&info, &digest, &dir, bindings, Path::new(bundle))?;
+    println!(
+        "exported {} ({} inputs, {} bindings) to {}",
+        manifest.key,
+        manifest.inputs.len(),
+        manifest.bindings.len(),
+        bundle
+    );
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--target", "--opt-level"], &["--debug"])?;
+    let [fqbn, bundle] = positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let tmp = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let info = fqbn_info(fqbn, tmp.path())?;
+    let factory = core_factory(&info, &options, &tmp.path().join("out"))?;
+    let dir = factory.core_archive_dir();
+    let manifest = core_bundle::import(&info, &factory.core_digest(), Path::new(bundle), &dir)?;
+    println!("imported {} to {}", manifest.key, dir.display());
+    Ok(())
+}
+
+/// the hook stages after linking, in the order of the arduino builder. the objcopy recipes run
+/// between `objcopy.preobjcopy` and `objcopy.postobjcopy`.
+const POSTLINK_STAGES: [&str; 6] = [
+    "linking.postlink",
+    "objcopy.preobjcopy",
+    "objcopy.postobjcopy",
+    "savehex.presavehex",
+    "savehex.postsavehex",
+    "postbuild",
+];
+
+/// the config and the firmware of `[<config yaml>] <elf>`, the config defaults to
+/// `ARDUINO_SDK_CONFIG` like in the build script. so the tool works as a cargo runner.
+fn config_and_elf(positional: &[String]) -> Result<(arduino_cli_util::Info, PathBuf), String> {
+    let (config, elf) = match positional {
+        [elf] => (
+            std::env::var("ARDUINO_SDK_CONFIG").map_err(|_| "no config given and ARDUINO_SDK_CONFIG is not set")?,
+            elf,
+        ),
+        [config, elf] => (config.to_owned(), elf),
+        _ => return Err(USAGE.to_string()),
+    };
+    let info = load_info(Path::new(&config))?;
+    let elf = arduino_cli_util::absolute(Path::new(elf)).map_err(|e| e.to_string())?;
+    if elf.is_file() == false {
+        return Err(format!("{}: no such file", elf.display()));
+    }
+    Ok((info, elf))
+}
+
+/// the platform recipes expect `{build.path}/{build.project_name}.elf`. rustc names the firmware
+/// `<name>.elf` on avr, but `<name>` on most other targets, then a `<name>.elf` copy is made.
+/// return (build path, project name).
+fn elf_layout(elf: &Path) -> Result<(PathBuf, String), String> {
+    let dir = elf.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let name = elf.file_name().unwrap_or_default().to_string_lossy().to_string();
+    match name.strip_suffix(".elf") {
+        Some(stem) => Ok((dir, stem.to_string())),
+        None => {
+            let copy = dir.join(format!("{}.elf", name));
+            std::fs::copy(elf, &copy).map_err(|e| format!("{}: {}", copy.display(), e))?;
+            Ok((dir, name))
+        }
+    }
+}
+
+/// run the `recipe.objcopy.<kind>.pattern` recipes of the platform (e.g. eep, hex, bin) on the
+/// firmware of `values`. return the `<build.path>/<build.project_name>.<kind>` files they wrote.
+fn run_objcopy(info: &arduino_cli_util::Info, values: &[(String, String)]) -> Result<Vec<PathBuf>, String> {
+    let value = |k: &str| values.iter().find(|(key, _)| key == k).map(|(_, v)| v.to_owned()).unwrap_or_default();
+    let mut outputs = vec![];
+    for (key, mut cmd) in compile_bindgen::recipe_commands(info, "recipe.objcopy.", values) {
+        let mut command = vec![cmd.get_program().to_string_lossy().to_string()];
+        command.extend(cmd.get_args().map(|a| a.to_string_lossy().to_string()));
+        eprintln!("{}", command.join(" "));
+        let output = cmd.output().map_err(|e| format!("{}: {}", command[0], e))?;
+        eprint!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        if output.status.success() == false {
+            return Err(format!("{} `{}` failed: {}", key, command.join(" "), output.status));
+        }
+        let kind = key["recipe.objcopy.".len()..].split('.').next().unwrap_or_default();
+        let file = Path::new(&value("build.path")).join(format!("{}.{}", value("build.project_name"), kind));
+        if file.exists() {
+            outputs.push(file);
+        }
+    }
+    Ok(outputs)
+}
+
+fn objcopy(args: &[String]) -> Result<(), String> {
+    let (_, positional) = parse_args(args, &[], &[])?;
+    let (info, elf) = config_and_elf(&positional)?;
+    let (build_path, project_name) = elf_layout(&elf)?;
+    let values = compile_bindgen::hook_values(&info, &build_path, &project_name);
+    for f in run_objcopy(&info, &values)? {
+        println!("{}", f.display());
+    }
+    Ok(())
+}
+
+/// bytes of the firmware, summed over the lines of the `recipe.size.pattern` output that
+/// `recipe.size.regex`, `recipe.size.regex.data` and `recipe.size.regex.eeprom` match
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SizeReport {
+    program: u64,
+    data: Option<u64>,
+    eeprom: Option<u64>,
+}
+
+/// the most a firmware may use, in percent of the board maximum. "size_budget" of the down-stream
+/// config, the `--flash-percent` and `--ram-percent` options win.
+#[derive(Debug, Clone, Default)]
+struct SizeBudget {
+    flash_percent: Option<u64>,
+    ram_percent: Option<u64>,
+}
+
+fn measure_size(info: &arduino_cli_util::Info, values: &[(String, String)]) -> Result<SizeReport, String> {
+    let (key, mut cmd) = compile_bindgen::recipe_command(info, "recipe.size.pattern", values)
+        .ok_or("the platform has no recipe.size.pattern")?;
+    let output = cmd.output().map_err(|e| format!("{}: {:?}: {}", key, cmd.get_program(), e))?;
+    if output.status.success() == false {
+        return Err(format!(
+            "{} failed: {}\n{}",
+            key,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    parse_size_output(info, &String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_size_output(info: &arduino_cli_util::Info, output: &str) -> Result<SizeReport, String> {
+    let sum = |key: &str| -> Result<Option<u64>, String> {
+        let Some(pattern) = info.get_var(key) else {
+            return Ok(None);
+        };
+        let re = regex::Regex::new(&pattern).map_err(|e| format!("{}: {}", key, e))?;
+        let total = output
+            .lines()
+            .filter_map(|l| re.captures(l))
+            .flat_map(|c| {
+                c.iter()
+                    .skip(1)
+                    .flatten()
+                    .filter_map(|m| m.as_str().parse::<u64>().ok())
+                    .collect::<Vec<_>>()
+            })
+            .sum();
+        Ok(Some(total))
+    };
+    Ok(SizeReport {
+        program: sum("recipe.size.regex")?.ok_or("the platform has no recipe.size.regex")?,
+        data: sum("recipe.size.regex.data")?,
+        eeprom: sum("recipe.size.regex.eeprom")?,
+    })
+}
+
+/// the lines the arduino IDE prints for `report`, and the reasons the firmware does not fit
+/// `upload.maximum_size`, `upload.maximum_data_size` or `budget`
+fn check_size(info: &arduino_cli_util::Info, report: &SizeReport, budget: &SizeBudget) -> (Vec<String>, Vec<String>) {
+    let maximum = |key: &str| info.get_var(key).and_then(|v| v.trim().parse::<u64>().ok()).filter(|m| *m > 0);
+    let (max_program, max_data) = (maximum("upload.maximum_size"), maximum("upload.maximum_data_size"));
+    let warn_data_percent = maximum("build.warn_data_percentage").unwrap_or(75);
+    let tips = "see https://support.arduino.cc/hc/en-us/articles/360013825179 for tips on reducing";
+    let (mut lines, mut errors) = (vec![], vec![]);
+
+    match max_program {
+        Some(max) => {
+            // truncated like the arduino IDE prints it, the budget is checked exactly
+            let percent = report.program * 100 / max;
+            lines.push(format!(
+                "Sketch uses {} bytes ({}%) of program storage space. Maximum is {} bytes.",
+                report.program, percent, max
+            ));
+            if report.program > max {
+                errors.push(format!("Sketch too big; {} it.", tips));
+            } else if let Some(b) = budget.flash_percent.filter(|b| report.program * 100 > b * max) {
+                errors.push(format!(
+                    "program storage use of {} bytes ({}%) is over the budget of {}% ({} bytes)",
+                    report.program,
+                    percent,
+                    b,
+                    b * max / 100
+                ));
+            }
+        }
+        None => lines.push(format!("Sketch uses {} bytes of program storage space.", report.program)),
+    }
+    if let Some(data) = report.data {
+        match max_data {
+            Some(max) => {
+                let percent = data * 100 / max;
+                lines.push(format!(
+                    "Global variables use {} bytes ({}%) of dynamic memory, leaving {} bytes for local variables. Maximum is {} bytes.",
+                    data,
+                    percent,
+                    max.saturating_sub(data),
+                    max
+                ));
+                if data > max {
+                    errors.push(format!("Not enough memory; {} your footprint.", tips));
+                } else if let Some(b) = budget.ram_percent.filter(|b| data * 100 > b * max) {
+                    errors.push(format!(
+                        "dynamic memory use of {} bytes ({}%) is over the budget of {}% ({} bytes)",
+                        data,
+                        percent,
+                        b,
+                        b * max / 100
+                    ));
+                } else if percent > warn_data_percent {
+                    lines.push("Low memory available, stability problems may occur.".to_string());
+                }
+            }
+            None => lines.push(format!("Global variables use {} bytes of dynamic memory.", data)),
+        }
+    }
+    if let Some(eeprom) = report.eeprom.filter(|e| *e > 0) {
+        lines.push(format!("EEPROM data uses {} bytes.", eeprom));
+    }
+    (lines, errors)
+}
+
+fn size(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--flash-percent", "--ram-percent"], &[])?;
+    let (info, elf) = config_and_elf(&positional)?;
+    let percent = |option: &str, key: &str| -> Result<Option<u64>, String> {
+        match options.get(option) {
+            Some(v) => v.parse::<u64>().map(Some).map_err(|_| format!("{}: '{}' is not a number", option, v)),
+            None => Ok(info
+                .config_value("size_budget")
+                .and_then(|b| b.get(key))
+                .and_then(|v| v.as_u64())),
+        }
+    };
+    let budget = SizeBudget {
+        flash_percent: percent("--flash-percent", "flash_percent")?,
+        ram_percent: percent("--ram-percent", "ram_percent")?,
+    };
+    let (build_path, project_name) = elf_layout(&elf)?;
+    let values = compile_bindgen::hook_values(&info, &build_path, &project_name);
+    let report = measure_size(&info, &values)?;
+    let (lines, errors) = check_size(&info, &report, &budget);
+    for l in lines {
+        println!("{}", l);
+    }
+    if errors.len() > 0 {
+        return Err(errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// a defined symbol of `nm -S`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    name: String,
+    size: u64,
+    /// the nm type letter, e.g. `T` text, `D` data, `B` bss
+    kind: char,
+}
+
+impl Symbol {
+    /// code and constants stay in flash, initialized data is copied from flash into RAM
+    fn in_flash(&self) -> bool {
+        "TtWwRrDd".contains(self.kind)
+    }
+    fn in_ram(&self) -> bool {
+        "DdBbCc".contains(self.kind)
+    }
+}
+
+/// memory of one component of the firmware, its symbols biggest first
+#[derive(Debug, Clone, Default)]
+struct Component {
+    name: String,
+    flash: u64,
+    ram: u64,
+    symbols: Vec<Symbol>,
+}
+
+/// `nm` of the platform toolchain: the C compiler of the recipes with `gcc` replaced by `nm`
+fn platform_nm(info: &arduino_cli_util::Info) -> String {
+    info.get_pat("recipe.c.o.pattern")
+        .and_then(|p| p.cmd.strip_suffix("gcc").map(|prefix| format!("{}nm", prefix)))
+        .unwrap_or("nm".to_string())
+}
+
+/// the defined symbols `nm --defined-only -S` prints for `file`, symbols without a size have size 0
+fn nm_symbols(nm: &str, file: &Path) -> Result<Vec<Symbol>, String> {
+    let output = Command::new(nm)
+        .args(["--defined-only", "-S"])
+        .arg(file)
+        .output()
+        .map_err(|e| format!("{}: {}", nm, e))?;
+    if output.status.success() == false {
+        return Err(format!(
+            "{} {}: {}",
+            nm,
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_nm(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `addr [size] type name` lines, archive member headers and blank lines are skipped
+fn parse_nm(output: &str) -> Vec<Symbol> {
+    output
+        .lines()
+        .filter_map(|l| {
+            let fields = l.split_whitespace().collect::<Vec<_>>();
+            let (size, kind, name) = match fields.as_slice() {
+                [_, size, kind, name] => (u64::from_str_radix(size, 16).ok()?, kind, name),
+                [_, kind, name] => (0, kind, name),
+                _ => return None,
+            };
+            let kind = kind.chars().next().filter(|_| kind.len() == 1)?;
+            Some(Symbol {
+                name: name.to_string(),
+                size,
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// the crate of a symbol of rustc: the first path component of the legacy `_ZN...17h<hash>E`
+/// mangling, or the crate root of the v0 `_R` mangling. `Some("")` for a rust symbol whose crate
+/// is not at hand, e.g. a legacy trait impl `_ZN<len>_$LT$...`.
+fn rust_crate(name: &str) -> Option<String> {
+    if let Some(v0) = name.strip_prefix("_R") {
+        return Some(v0_crate(v0.trim_start_matches(|c: char| c.is_ascii_digit())).unwrap_or_default());
+    }
+    let legacy = name.strip_prefix("_ZN")?.strip_suffix('E')?;
+    let hash = legacy.get(legacy.len().checked_sub(19)?..)?;
+    if hash.starts_with("17h") == false || hash[3..].chars().all(|c| c.is_ascii_hexdigit()) == false {
+        return None;
+    }
+    let first = mangled_ident(legacy, false).unwrap_or_default();
+    match first.starts_with('_') || first.contains('$') {
+        true => Some(String::new()),
+        false => Some(first.to_string()),
+    }
+}
+
+/// the crate root of a v0 `<path>`: the `C` below the nested paths (`N`), the impl paths (`M`,
+/// `X`) and the generic args (`I`)
+fn v0_crate(mut path: &str) -> Option<String> {
+    // `s<base-62-number>_`
+    fn skip_disambiguator(p: &str) -> Option<&str> {
+        match p.strip_prefix('s') {
+            Some(rest) => rest.split_once('_').map(|(_, rest)| rest),
+            None => Some(p),
+        }
+    }
+    loop {
+        let (tag, rest) = (path.chars().next()?, &path[1..]);
+        path = match tag {
+            'N' => rest.get(1..)?,
+            'I' => rest,
+            'M' | 'X' => skip_disambiguator(rest)?,
+            'C' => return mangled_ident(skip_disambiguator(rest)?.trim_start_matches('u'), true).map(str::to_string),
+            _ => return None,
+        };
+    }
+}
+
+/// the `<decimal length><ident>` at the start of `s`. v0 puts a `_` between the length and an
+/// ident that starts with a digit or `_`, which `v0` skips.
+fn mangled_ident(s: &str, v0: bool) -> Option<&str> {
+    let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+    let len = s[..digits].parse::<usize>().ok()?;
+    let rest = &s[digits..];
+    let rest = match v0 {
+        true => rest.strip_prefix('_').unwrap_or(rest),
+        false => rest,
+    };
+    rest.get(..len)
+}
+
+/// split the sized symbols of the firmware into the archives that define them (in the order of
+/// `archives`, the first wins), the rust crates (`rust <crate>`) and `other` (libc, libgcc, the
+/// vector table, ...). a local symbol of an archive (a lowercase nm type, like a `static`) owns
+/// nothing, its name may be reused by any other object.
+/// components without memory are left out, the biggest comes first.
+fn attribute(firmware: &[Symbol], archives: &[(String, Vec<Symbol>)]) -> Vec<Component> {
+    let mut owner = HashMap::<&str, &str>::new();
+    for (name, symbols) in archives {
+        for s in symbols.iter().filter(|s| s.kind.is_ascii_uppercase()) {
+            owner.entry(s.name.as_str()).or_insert(name.as_str());
+        }
+    }
+    let mut components = std::collections::BTreeMap::<String, Component>::new();
+    for s in firmware.iter().filter(|s| s.size > 0 && (s.in_flash() || s.in_ram())) {
+        let name = match (owner.get(s.name.as_str()), rust_crate(&s.name)) {
+            (Some(archive), _) => archive.to_string(),
+            (None, Some(krate)) if krate.len() > 0 => format!("rust {}", krate),
+            (None, Some(_)) => "rust".to_string(),
+            (None, None) => "other".to_string(),
+        };
+        let c = components.entry(name.to_owned()).or_insert_with(|| Component {
+            name,
+            ..Default::default()
+        });
+        if s.in_flash() {
+            c.flash += s.size;
+        }
+        if s.in_ram() {
+            c.ram += s.size;
+        }
+        c.symbols.push(s.to_owned());
+    }
+    let mut components = components.into_values().collect::<Vec<_>>();
+    for c in components.iter_mut() {
+        c.symbols.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    }
+    components.sort_by(|a, b| (b.flash + b.ram).cmp(&(a.flash + a.ram)).then_with(|| a.name.cmp(&b.name)));
+    components
+}
+
+/// the newest `arduino_build_report.json` of this crate below the target dir of `elf`,
+/// `target/<triple>/<profile>/build/arduino-sdk-sys-<hash>/out`
+fn find_build_report(elf: &Path) -> Option<PathBuf> {
+    let build = elf.parent()?.join("build");
+    std::fs::read_dir(build)
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("arduino-sdk-sys-"))
+        .map(|e| e.path().join("out").join("arduino_build_report.json"))
+        .filter_map(|p| Some((std::fs::metadata(&p).ok()?.modified().ok()?, p)))
+        .max()
+        .map(|(_, p)| p)
+}
+
+/// (name, path) of the archives of a build report, in link order
+fn report_archives(report: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let json = std::fs::read_to_string(report).map_err(|e| format!("{}: {}", report.display(), e))?;
+    let v = serde_json::from_str::<serde_json::Value>(&json).map_err(|e| format!("{}: {}", report.display(), e))?;
+    Ok(v["archives"]
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|a| Some((a["name"].as_str()?.to_string(), PathBuf::from(a["path"].as_str()?))))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn memory(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--report", "--top", "--nm"], &[])?;
+    let (info, elf) = config_and_elf(&positional)?;
+    let top = match options.get("--top") {
+        Some(n) => n.parse::<usize>().map_err(|_| format!("--top: '{}' is not a number", n))?,
+        None => 5,
+    };
+    let nm = options.get("--nm").cloned().unwrap_or_else(|| platform_nm(&info));
+    let report = match options.get("--report") {
+        Some(r) => PathBuf::from(r),
+        None => find_build_report(&elf).ok_or(format!(
+            "no arduino_build_report.json below {}, pass --report",
+            elf.parent().unwrap_or(Path::new(".")).display()
+        ))?,
+    };
+
+    let mut archives = vec![];
+    for (name, path) in report_archives(&report)? {
+        archives.push((name, nm_symbols(&nm, &path)?));
+    }
+    let components = attribute(&nm_symbols(&nm, &elf)?, &archives);
+
+    println!("{:<32} {:>10} {:>10}", "component", "flash", "ram");
+    for c in &components {
+        println!("{:<32} {:>10} {:>10}", c.name, c.flash, c.ram);
+    }
+    println!(
+        "{:<32} {:>10} {:>10}",
+        "total",
+        components.iter().map(|c| c.flash).sum::<u64>(),
+        components.iter().map(|c| c.ram).sum::<u64>()
+    );
+    for c in components.iter().filter(|_| top > 0) {
+        println!("\n{}:", c.name);
+        for s in c.symbols.iter().take(top) {
+            let region = match (s.in_flash(), s.in_ram()) {
+                (true, true) => "flash+ram",
+                (true, false) => "flash",
+                _ => "ram",
+            };
+            println!("  {:>8} {:<9} {}", s.size, region, s.name);
+        }
+    }
+    Ok(())
+}
+
+/// the upload tool of the board: `upload.tool.<port protocol>`, `upload.tool.default` or the
+/// older `upload.tool`, without the `<vendor>:` prefix of a tool of another platform
+fn upload_tool(info: &arduino_cli_util::Info, protocol: &str) -> Option<String> {
+    [format!("upload.tool.{}", protocol), "upload.tool.default".to_string(), "upload.tool".to_string()]
+        .iter()
+        .find_map(|k| info.get_var(k))
+        .map(|t| t.rsplit(':').next().unwrap_or_default().to_string())
+}
+
+#[derive(Debug, Clone, Default)]
+struct UploadOptions {
+    port: Option<String>,
+    verbose: bool,
+    verify: bool,
+}
+
+/// the `tools.<tool>.upload.pattern` command for the firmware of `values`, resolved like arduino-cli
+/// does: the `tools.<tool>.*` properties are visible without their prefix (`{cmd.path}`,
+/// `{config.path}`), `{upload.verbose}` and `{upload.verify}` are the `upload.params.*` of the tool,
+/// `{serial.port}` and `{upload.port.address}` the port.
+fn upload_command(
+    info: &arduino_cli_util::Info,
+    values: &[(String, String)],
+    options: &UploadOptions,
+) -> Result<(String, Command), String> {
+    let tool = upload_tool(info, "serial").ok_or("the board has no upload.tool")?;
+    let prefix = format!("tools.{}.", tool);
+    let mut tool_values = info
+        .orig_properties
+        .iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?.to_string(), v.to_owned())))
+        .collect::<Vec<_>>();
+    tool_values.sort();
+    let param = |name: &str| {
+        let key = format!("upload.params.{}", name);
+        tool_values.iter().find(|(k, _)| k == &key).map(|(_, v)| v.to_owned()).unwrap_or_default()
+    };
+    let mut values = values.to_vec();
+    values.push(("upload.verbose".to_string(), param(if options.verbose { "verbose" } else { "quiet" })));
+    values.push(("upload.verify".to_string(), param(if options.verify { "verify" } else { "noverify" })));
+    if let Some(port) = &options.port {
+        let file = Path::new(port).file_name().unwrap_or_default().to_string_lossy().to_string();
+        values.push(("serial.port".to_string(), port.to_owned()));
+        values.push(("serial.port.file".to_string(), file));
+        values.push(("upload.port.address".to_string(), port.to_owned()));
+        values.push(("upload.port.protocol".to_string(), "serial".to_string()));
+    }
+    values.extend(tool_values);
+
+    let key = format!("{}upload.pattern", prefix);
+    let (key, cmd) = compile_bindgen::recipe_command(info, &key, &values).ok_or(format!("the platform has no {}", key))?;
+    let unresolved_port = cmd
+        .get_args()
+        .any(|a| ["{serial.port", "{upload.port."].iter().any(|p| a.to_string_lossy().contains(p)));
+    if unresolved_port {
+        return Err(format!("{} needs an upload port, pass --port or set ARDUINO_SDK_PORT", key));
+    }
+    Ok((key, cmd))
+}
+
+fn upload(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--port"], &["--verbose", "--verify", "--print"])?;
+    let (info, firmware) = config_and_elf(&positional)?;
+    let upload_options = UploadOptions {
+        port: options.get("--port").cloned().or_else(|| std::env::var("ARDUINO_SDK_PORT").ok()),
+        verbose: options.contains_key("--verbose"),
+        verify: options.contains_key("--verify"),
+    };
+    let print = options.contains_key("--print");
+    // an .elf, or the target name without extension, is converted first; a .hex or .bin is uploaded as is
+    let is_elf = matches!(firmware.extension().and_then(|e| e.to_str()), None | Some("elf"));
+    let (build_path, project_name) = match is_elf {
+        true => elf_layout(&firmware)?,
+        false => (
+            firmware.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            firmware.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        ),
+    };
+    let values = compile_bindgen::hook_values(&info, &build_path, &project_name);
+    if is_elf && print == false {
+        run_objcopy(&info, &values)?;
+    }
+    if info.get_var("upload.use_1200bps_touch").as_deref() == Some("true") {
+        eprintln!("warning: the board resets into its bootloader by a 1200 bps touch of the port, which is not done here");
+    }
+
+    let (key, mut cmd) = upload_command(&info, &values, &upload_options)?;
+    let mut command = vec![cmd.get_program().to_string_lossy().to_string()];
+    command.extend(cmd.get_args().map(|a| a.to_string_lossy().to_string()));
+    if print {
+        println!("{}", command.join(" "));
+        return Ok(());
+    }
+    eprintln!("{}", command.join(" "));
+    let status = cmd.status().map_err(|e| format!("{}: {}", command[0], e))?;
+    if status.success() == false {
+        return Err(format!("{} `{}` failed: {}", key, command.join(" "), status));
+    }
+    Ok(())
+}
+
+fn postbuild(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &[], &["--dry-run"])?;
+    let (info, elf) = config_and_elf(&positional)?;
+    let mut policy = info.hook_policy();
+    policy.dry_run |= options.contains_key("--dry-run");
+    let (build_path, project_name) = elf_layout(&elf)?;
+    let values = compile_bindgen::hook_values(&info, &build_path, &project_name);
+    for stage in POSTLINK_STAGES {
+        let (reports, result) = compile_bindgen::run_platform_hooks(&info, stage, &values, &policy);
+        for r in reports {
+            match r.skipped {
+                Some(reason) => eprintln!("warning: {}", reason),
+                None => eprintln!("{}: {}", r.stage, r.command.join(" ")),
+            }
+        }
+        result?;
+        if stage == "objcopy.preobjcopy" {
+            for f in run_objcopy(&info, &values)? {
+                println!("{}", f.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the custom rust target of an avr board, like the hand written `avr-specs/avr-<mcu>.json`.
+/// the linker is the program of `recipe.c.combine.pattern` unless `linker` is given, and
+/// `build.f_cpu` goes to the description.
+fn avr_target_spec(info: &arduino_cli_util::Info, linker: Option<&str>) -> Result<serde_json::Value, String> {
+    if info.get_arch() != "avr" {
+        return Err(format!("{} is no avr board, target specs are generated for avr only", info.get_fqbn()));
+    }
+    let mcu = info.get_var("build.mcu").ok_or(format!("{} has no build.mcu", info.get_fqbn()))?;
+    let linker = match linker {
+        Some(l) => l.to_string(),
+        None => info
+            .get_pat("recipe.c.combine.pattern")
+            .map(|p| p.cmd)
+            .unwrap_or("avr-gcc".to_string()),
+    };
+    let description = match info.get_var("build.f_cpu") {
+        Some(f) => format!("{} ({}, {} Hz)", info.get_fqbn(), mcu, f.trim_end_matches(['L', 'l', 'U', 'u'])),
+        None => format!("{} ({})", info.get_fqbn(), mcu),
+    };
+    Ok(serde_json::json!({
+        "arch": "avr",
+        "atomic-cas": false,
+        "cpu": mcu,
+        "data-layout": "e-P1-p:16:8-i8:8-i16:8-i32:8-i64:8-f32:8-f64:8-n8-a:8",
+        "eh-frame-header": false,
+        "exe-suffix": ".elf",
+        "executables": true,
+        "late-link-args": { "gcc": ["-lgcc"] },
+        "linker": linker,
+        "llvm-target": "avr-unknown-unknown",
+        "max-atomic-width": 8,
+        "metadata": { "description": description },
+        "no-default-libraries": false,
+        "pre-link-args": { "gcc": [format!("-mmcu={}", mcu)] },
+        "target-c-int-width": "16",
+        "target-pointer-width": "16"
+    }))
+}
+
+fn target_spec(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--out", "--linker", "--check"], &[])?;
+    let config = match positional.as_slice() {
+        [] => std::env::var("ARDUINO_SDK_CONFIG").map_err(|_| "no config given and ARDUINO_SDK_CONFIG is not set")?,
+        [config] => config.to_owned(),
+        _ => return Err(USAGE.to_string()),
+    };
+    let info = load_info(Path::new(&config))?;
+
+    if let Some(target) = options.get("--check") {
+        // a spec file is checked by its "cpu", a target name by its `<arch>-<cpu>` name
+        let (name, json) = match target.ends_with(".json") {
+            true => {
+                let json = std::fs::read_to_string(target).map_err(|e| format!("{}: {}", target, e))?;
+                let spec = serde_json::from_str::<serde_json::Value>(&json).map_err(|e| format!("{}: {}", target, e))?;
+                let cpu = spec["cpu"].as_str().ok_or(format!("{}: no cpu", target))?;
+                (format!("{}-{}", spec["arch"].as_str().unwrap_or("avr"), cpu), Some(json))
+            }
+            false => (target.to_owned(), None),
+        };
+        // an explicit check also fails on the name
+        if let Some(w) = compile_bindgen::check_target_mcu(&info, &name, &[], json.as_deref())? {
+            return Err(w);
+        }
+        println!("{}: ok", target);
+        return Ok(());
+    }
+
+    let spec = avr_target_spec(&info, options.get("--linker").map(String::as_str))?;
+    let json = serde_json::to_string_pretty(&spec).map_err(|e| e.to_string())? + "\n";
+    match options.get("--out").map(PathBuf::from) {
+        Some(dir) if dir.is_dir() => {
+            let file = dir.join(format!("avr-{}.json", spec["cpu"].as_str().unwrap_or_default()));
+            std::fs::write(&file, json).map_err(|e| format!("{}: {}", file.display(), e))?;
+            println!("{}", file.display());
+        }
+        Some(file) => std::fs::write(&file, json).map_err(|e| format!("{}: {}", file.display(), e))?,
+        None => print!("{}", json),
+    }
+    Ok(())
+}
+
+fn show(args: &[String]) -> Result<(), String> {
+    let (_, positional) = parse_args(args, &[], &[])?;
+    let [config] = positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let info = load_info(Path::new(config))?;
+
+    println!("fqbn: {}", info.get_fqbn());
+    println!(
+        "platform: {} {}",
+        info.get_var("runtime.platform.path").unwrap_or_default(),
+        info.get_var("version").unwrap_or_default()
+    );
+    println!("core archive dir: {}/<core digest>", info.default_archive_dir().display());
+    println!("object cache dir: {}", info.object_cache_dir().display());
+    println!("recipes:");
+    for key in RECIPES {
+        if let Some(p) = info.get_pat(key) {
+            println!("  {}: {} {}", key, p.cmd, p.flags.join(" "));
+        }
+    }
+    println!("libraries (link order):");
+    for l in info.libraries() {
+        println!(
+            "  {} {} ({}) {}",
+            l.name,
+            l.get_property("version").unwrap_or("-".to_string()),
+            match l.location {
+                arduino_cli_util::LibraryLocation::User => "user",
+                arduino_cli_util::LibraryLocation::Platform => "platform",
+            },
+            l.root.display()
+        );
+    }
+    Ok(())
+}
+
+fn clean(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--cache-dir"], &[])?;
+    if positional.len() > 0 {
+        return Err(USAGE.to_string());
+    }
+    let dir = cache_lib_dir(&options)?;
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        println!("removed {}", dir.display());
+    }
+    Ok(())
+}
+
+fn gc(args: &[String]) -> Result<(), String> {
+    let (options, positional) = parse_args(args, &["--cache-dir", "--days"], &[])?;
+    if positional.len() > 0 {
+        return Err(USAGE.to_string());
+    }
+    let days = match options.get("--days") {
+        Some(d) => d.parse::<u64>().map_err(|_| format!("--days: '{}' is not a number", d))?,
+        None => 30,
+    };
+    let dir = cache_lib_dir(&options)?;
+    let installed = installed_platforms().ok_or("can not list the installed platforms by arduino-cli")?;
+
+    let mut removed = stale_platform_dirs(&dir, &installed);
+    removed.extend(stale_objects(
+        &dir.join("objects"),
+        SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60),
+    ));
+    for p in &removed {
+        let r = if p.is_dir() {
+            std::fs::remove_dir_all(p)
+        } else {
+            std::fs::remove_file(p)
+        };
+        match r {
+            Ok(_) => println!("removed {}", p.display()),
+            Err(e) => eprintln!("warning: {}: {}", p.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// `(id, version)` of the platforms arduino-cli has installed, e.g. `("arduino:avr", "1.8.6")`
+fn installed_platforms() -> Option<Vec<(String, String)>> {
+    let output = Command::new("arduino-cli")
+        .args(["core", "list", "--format", "yaml"])
+        .output()
+        .ok()?;
+    if output.status.success() == false {
+        return None;
+    }
+    parse_core_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// parse `arduino-cli core list --format yaml`. arduino-cli 1.x writes
+/// `platforms: [{id, installed_version}]`, older versions a bare `[{id, installed}]`.
+fn parse_core_list(yaml: &str) -> Option<Vec<(String, String)>> {
+    let v = serde_yaml::from_str::<serde_yaml::Value>(yaml).ok()?;
+    let list = match v.get("platforms") {
+        Some(p) => p.as_sequence().cloned().unwrap_or_default(),
+        None if v.is_null() => vec![],
+        None => v.as_sequence()?.to_owned(),
+    };
+    Some(
+        list.iter()
+            .filter_map(|p| {
+                let id = p.get("id")?.as_str()?;
+                let version = p
+                    .get("installed_version")
+                    .or_else(|| p.get("installed"))?
+                    .as_str()?;
+                Some((id.to_string(), version.to_string()))
+            })
+            .collect(),
+    )
+}
+
+/// the `<lib_dir>/<packager>/<arch>/<version>` dirs of the platform versions not in `installed`
+fn stale_platform_dirs(lib_dir: &Path, installed: &[(String, String)]) -> Vec<PathBuf> {
+    let sub_dirs = |d: &Path| -> Vec<PathBuf> {
+        let mut v = std::fs::read_dir(d)
+            .map(|r| r.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        v.sort();
+        v
+    };
+    let name = |p: &Path| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    let mut result = vec![];
+    for packager in sub_dirs(lib_dir) {
+        if name(&packager) == "objects" {
+            continue;
+        }
+        for arch in sub_dirs(&packager) {
+            let id = format!("{}:{}", name(&packager), name(&arch));
+            for version in sub_dirs(&arch) {
+                let kept = installed.iter().any(|(i, v)| *i == id && *v == name(&version));
+                if kept == false {
+                    result.push(version);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// the object cache entries (`.o` and `.deps`) whose headers changed or are gone,
+/// or that were not used since `unused_since`
+fn stale_objects(objects_dir: &Path, unused_since: SystemTime) -> Vec<PathBuf> {
+    let mut entries = std::fs::read_dir(objects_dir)
+        .map(|r| {
+            r.flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "deps"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    entries.sort();
+
+    let mut result = vec![];
+    for deps in entries {
+        let obj = deps.with_extension("o");
+        let valid = std::fs::read_to_string(&deps)
+            .ok()
+            .and_then(|s| object_cache::check_deps_manifest(&s))
+            .is_some();
+        let used = std::fs::metadata(&obj)
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t >= unused_since);
+        if valid == false || used == false {
+            if obj.exists() {
+                result.push(obj);
+            }
+            result.push(deps);
+        }
+    }
+    result
+}
+
+fn doctor(args: &[String]) -> Result<(), String> {
+    let (_, positional) = parse_args(args, &[], &[])?;
+    if positional.len() > 1 {
+        return Err(USAGE.to_string());
+    }
+    let mut failed = false;
+    let mut check = |what: &str, result: Result<String, String>| match result {
+        Ok(s) => println!("ok      {}: {}", what, s),
+        Err(e) => {
+            println!("FAILED  {}: {}", what, e);
+            failed = true;
+        }
+    };
+
+    check(
+        "arduino-cli",
+        version_of("arduino-cli", "version").map_err(|e| format!("{}, it must be in PATH", e)),
+    );
+    check(
+        "sketchbook",
+        arduino_cli_util::get_user().ok_or("`arduino-cli config dump` has no directories.user".to_string()),
+    );
+    check(
+        "installed platforms",
+        match installed_platforms() {
+            Some(v) if v.len() > 0 => Ok(v
+                .iter()
+                .map(|(id, version)| format!("{} {}", id, version))
+                .collect::<Vec<_>>()
+                .join(", ")),
+            Some(_) => Err("none, install one by `arduino-cli core install <packager:arch>`".to_string()),
+            None => Err("`arduino-cli core list` failed".to_string()),
+        },
+    );
+    if let Some(config) = positional.first() {
+        match load_info(Path::new(config)) {
+            Ok(info) => {
+                check("board", Ok(info.get_fqbn()));
+                for key in RECIPES {
+                    if let Some(p) = info.get_pat(key) {
+                        check(key, version_of(&p.cmd, "--version"));
+                    }
+                }
+            }
+            Err(e) => check("board", Err(e)),
+        }
+    }
+    // libclang is only needed by the native_bindgen feature, a missing one is not a failure
+    match libclang_dir() {
+        Some(d) => println!("ok      libclang: {}", d.display()),
+        None => println!("warning libclang: not found, set LIBCLANG_PATH to use the native_bindgen feature"),
+    }
+
+    if failed {
+        return Err("some checks failed".to_string());
+    }
+    Ok(())
+}
+
+/// the first line `cmd arg` prints
+fn version_of(cmd: &str, arg: &str) -> Result<String, String> {
+    let output = Command::new(cmd)
+        .arg(arg)
+        .output()
+        .map_err(|e| format!("{}: {}", cmd, e))?;
+    if output.status.success() == false {
+        return Err(format!("`{} {}` failed with {}", cmd, arg, output.status));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+/// the dir of libclang as bindgen looks it up: `LIBCLANG_PATH`, `llvm-config --libdir`,
+/// then the usual system dirs
+fn libclang_dir() -> Option<PathBuf> {
+    let mut dirs = vec![];
+    if let Ok(d) = std::env::var("LIBCLANG_PATH") {
+        dirs.push(PathBuf::from(d));
+    }
+    if let Ok(d) = version_of("llvm-config", "--libdir") {
+        dirs.push(PathBuf::from(d));
+    }
+    for d in ["/usr/lib", "/usr/lib64", "/usr/local/lib", "/usr/lib/x86_64-linux-gnu"] {
+        dirs.push(PathBuf::from(d));
+    }
+    dirs.into_iter().find(|d| {
+        std::fs::read_dir(d).is_ok_and(|r| {
+            r.flatten().any(|e| {
+                let n = e.file_name().to_string_lossy().to_string();
+                n.starts_with("libclang.") || n.starts_with("libclang-") || n == "libclang.dll"
+            })
+        })
+    })
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    #[test]
+    fn objcopy_writes_next_to_the_elf() {
+        let root = tempfile::tempdir().unwrap();
+        let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\n");
+        for (kind, flags) in [("hex", "-O ihex"), ("eep", "-j .eeprom"), ("bin", "-O binary")] {
+            info.orig_properties.insert(
+                format!("recipe.objcopy.{}.pattern", kind),
+                format!(
+                    "sh -c \"echo {} > '{{build.path}}/{{build.project_name}}.{}'\" \"{{build.path}}/{{build.project_name}}.elf\"",
+                    flags, kind
+                ),
+            );
+        }
+        // a target without the .elf extension
+        let elf = root.path().join("target/thumbv6m-none-eabi/release/blink");
+        arduino_cli_util::tests::write_file(&elf, "ELF");
+
+        let (build_path, project_name) = elf_layout(&elf).unwrap();
+        assert_eq!((build_path.as_path(), project_name.as_str()), (elf.parent().unwrap(), "blink"));
+        assert!(elf.with_file_name("blink.elf").exists());
+        let values = compile_bindgen::hook_values(&info, &build_path, &project_name);
+        let outputs = run_objcopy(&info, &values).unwrap();
+        assert_eq!(outputs, ["blink.bin", "blink.eep", "blink.hex"].map(|f| elf.with_file_name(f)));
+        assert_eq!(std::fs::read_to_string(elf.with_file_name("blink.hex")).unwrap(), "-O ihex\n");
+
+        info.orig_properties
+            .insert("recipe.objcopy.hex.pattern".to_string(), "false".to_string());
+        let err = run_objcopy(&info, &values).unwrap_err();
+        assert!(err.starts_with("recipe.objcopy.hex.pattern `false` failed"), "{}", err);
+    }
+
+    /// an uno with the size recipes of arduino:avr
+    fn uno_size_info(root: &Path) -> arduino_cli_util::Info {
+        let mut info = arduino_cli_util::tests::test_info(root, "fqbn: arduino:avr:uno\n");
+        for (k, v) in [
+            ("recipe.size.regex", r"^(?:\.text|\.data|\.bootloader)\s+([0-9]+).*"),
+            ("recipe.size.regex.data", r"^(?:\.data|\.bss|\.noinit)\s+([0-9]+).*"),
+            ("recipe.size.regex.eeprom", r"^(?:\.eeprom)\s+([0-9]+).*"),
+            ("upload.maximum_size", "32256"),
+            ("upload.maximum_data_size", "2048"),
+        ] {
+            info.orig_properties.insert(k.to_string(), v.to_string());
+        }
+        info
+    }
+
+    #[test]
+    fn size_is_checked_against_the_board() {
+        let root = tempfile::tempdir().unwrap();
+        let info = uno_size_info(root.path());
+        let avr_size = "blink.elf  :\nsection           size      addr\n.data               10   8388864\n.text              924         0\n.bss                 9   8388874\n.comment            17         0\nTotal             1003\n";
+        let report = parse_size_output(&info, avr_size).unwrap();
+        assert_eq!(report, SizeReport { program: 934, data: Some(19), eeprom: Some(0) });
+
+        let (lines, errors) = check_size(&info, &report, &SizeBudget::default());
+        assert_eq!(
+            lines,
+            [
+                "Sketch uses 934 bytes (2%) of program storage space. Maximum is 32256 bytes.",
+                "Global variables use 19 bytes (0%) of dynamic memory, leaving 2029 bytes for local variables. Maximum is 2048 bytes.",
+            ]
+        );
+        assert!(errors.is_empty());
+
+        let budget = SizeBudget { flash_percent: Some(1), ram_percent: None };
+        let (_, errors) = check_size(&info, &report, &budget);
+        assert_eq!(errors, ["program storage use of 934 bytes (2%) is over the budget of 1% (322 bytes)"]);
+        // 2.9% is over a budget of 2%, though it prints as 2%
+        let budget = SizeBudget { flash_percent: Some(2), ram_percent: Some(0) };
+        let (_, errors) = check_size(&info, &report, &budget);
+        assert_eq!(
+            errors,
+            [
+                "program storage use of 934 bytes (2%) is over the budget of 2% (645 bytes)",
+                "dynamic memory use of 19 bytes (0%) is over the budget of 0% (0 bytes)",
+            ]
+        );
+
+        let big = SizeReport { program: 40000, data: Some(1900), eeprom: Some(12) };
+        let (lines, errors) = check_size(&info, &big, &SizeBudget::default());
+        assert_eq!(lines[2], "Low memory available, stability problems may occur.");
+        assert_eq!(lines[3], "EEPROM data uses 12 bytes.");
+        assert!(errors[0].starts_with("Sketch too big;"));
+        let huge = SizeReport { program: 100, data: Some(3000), eeprom: None };
+        let (_, errors) = check_size(&info, &huge, &SizeBudget::default());
+        assert!(errors[0].starts_with("Not enough memory;"));
+    }
+
+    #[test]
+    fn memory_is_attributed_to_archives_and_rust() {
+        let core = parse_nm(
+            "\nwiring.c.o:\n00000000 00000010 T millis\n00000000 00000004 B timer0_millis\n\nmain.cpp.o:\n00000000 T main\n",
+        );
+        let servo = parse_nm("Servo.cpp.o:\n00000000 00000020 T _ZN5Servo5writeEi\n00000000 00000006 d servos\n");
+        let firmware = parse_nm(concat!(
+            "00000068 00000010 T millis\n",
+            "00800100 00000004 B timer0_millis\n",
+            "00000100 00000020 T _ZN5Servo5writeEi\n",
+            "00800104 00000006 d servos\n",
+            "00000200 00000040 t _ZN4demo4main17h0123456789abcdefE\n",
+            "00000240 00000030 t _RNvMs_NtCs1234_4core3fmtNtB4_9Formatter3pad\n",
+            "00000270 00000002 T _ZN4core9panicking5panic17h0123456789abcdefE\n",
+            "00000280 00000010 t _ZN53_$LT$T$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE\n",
+            "00000300 00000008 T __udivmodsi4\n",
+            "00000000 T __vectors\n",
+        ));
+        let archives = vec![("arduino_Servo".to_string(), servo), ("arduino_core".to_string(), core)];
+        let components = attribute(&firmware, &archives);
+        let summary = components
+            .iter()
+            .map(|c| (c.name.as_str(), c.flash, c.ram, c.symbols.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("rust demo", 0x40, 0, 1),
+                ("rust core", 0x32, 0, 2),
+                ("arduino_Servo", 0x20, 0, 1),
+                ("arduino_core", 0x10, 4, 2),
+                // the static `servos` of Servo.cpp is not owned by its name
+                ("other", 0x0e, 6, 2),
+                ("rust", 0x10, 0, 1)
+            ]
+        );
+        assert_eq!(components[2].symbols[0].name, "_ZN5Servo5writeEi");
+        assert_eq!(rust_crate("_RNvCs1234_4demo4main").as_deref(), Some("demo"));
+        assert_eq!(rust_crate("_RINvNtCs1_5alloc3vec3newNtCs2_4demo5ThingEB4_").as_deref(), Some("alloc"));
+        assert_eq!(rust_crate("_ZN5Servo5writeEi"), None);
+        assert_eq!(rust_crate("millis"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn upload_command_runs_the_tool_of_the_board() {
+        let root = tempfile::tempdir().unwrap();
+        let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\n");
+        let tools = root.path().join("tools/avrdude");
+        let pattern = "\"{cmd.path}\" \"-C{config.path}\" {upload.verbose} {upload.verify} -p{build.mcu} -c{upload.protocol} \"-P{serial.port}\" -b{upload.speed} -D \"-Uflash:w:{build.path}/{build.project_name}.hex:i\"";
+        for (k, v) in [
+            ("upload.tool", "avrdude"),
+            ("upload.tool.default", "arduino:avrdude"),
+            ("upload.protocol", "arduino"),
+            ("upload.speed", "115200"),
+            ("build.mcu", "atmega328p"),
+            ("runtime.tools.avrdude.path", tools.to_str().unwrap()),
+            ("tools.avrdude.path", "{runtime.tools.avrdude.path}"),
+            ("tools.avrdude.cmd.path", "{path}/bin/avrdude"),
+            ("tools.avrdude.config.path", "{path}/etc/avrdude.conf"),
+            ("tools.avrdude.upload.params.verbose", "-v -V"),
+            ("tools.avrdude.upload.params.quiet", "-q -q"),
+            ("tools.avrdude.upload.params.verify", ""),
+            ("tools.avrdude.upload.params.noverify", "-V"),
+            ("tools.avrdude.upload.pattern", pattern),
+        ] {
+            info.orig_properties.insert(k.to_string(), v.to_string());
+        }
+        // a stub programmer that records its arguments
+        let log = root.path().join("args.txt");
+        let stub = tools.join("bin/avrdude");
+        arduino_cli_util::tests::write_file(&stub, &format!("#!/bin/sh\necho \"$@\" > '{}'\n", log.display()));
+        std::fs::set_permissions(&stub, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+        let out = root.path().join("target/avr-none/release");
+        let values = compile_bindgen::hook_values(&info, &out, "blink");
+        let err = upload_command(&info, &values, &UploadOptions::default()).unwrap_err();
+        assert!(err.contains("needs an upload port"), "{}", err);
+
+        let options = UploadOptions { port: Some("/dev/ttyACM0".to_string()), ..Default::default() };
+        let (key, mut cmd) = upload_command(&info, &values, &options).unwrap();
+        assert_eq!(key, "tools.avrdude.upload.pattern");
+        assert_eq!(cmd.get_program(), stub.as_os_str());
+        assert!(cmd.status().unwrap().success());
+        let args = format!(
+            "-C{}/etc/avrdude.conf -q -q -V -patmega328p -carduino -P/dev/ttyACM0 -b115200 -D -Uflash:w:{}/blink.hex:i\n",
+            tools.display(),
+            out.display()
+        );
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), args);
+
+        let options = UploadOptions { verbose: true, verify: true, ..options };
+        let (_, cmd) = upload_command(&info, &values, &options).unwrap();
+        let args = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect::<Vec<_>>();
+        assert_eq!(args[1..3], ["-v", "-V"]);
+        assert_eq!(args[3], "-patmega328p");
+    }
+
+    #[test]
+    fn avr_target_spec_matches_the_hand_written_one() {
+        let root = tempfile::tempdir().unwrap();
+        let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\n");
+        info.orig_properties.insert("build.mcu".to_string(), "atmega328p".to_string());
+        info.orig_properties.insert("build.f_cpu".to_string(), "16000000L".to_string());
+
+        let mut spec = avr_target_spec(&info, Some("avr-gcc")).unwrap();
+        let description = spec.as_object_mut().unwrap().remove("metadata").unwrap();
+        assert_eq!(description["description"], "arduino:avr:uno (atmega328p, 16000000 Hz)");
+        let demo = include_str!("../../_examples/demo1/avr-specs/avr-atmega328p.json");
+        assert_eq!(spec, serde_json::from_str::<serde_json::Value>(demo).unwrap());
+
+        let esp32 = arduino_cli_util::tests::test_info(root.path(), "fqbn: esp32:esp32:esp32\n");
+        assert!(avr_target_spec(&esp32, None).is_err());
+    }
+
+    #[test]
+    fn parse_core_list_reads_both_formats() {
+        let new = "platforms:\n  - id: arduino:avr\n    installed_version: 1.8.6\n    latest_version: 1.8.6\n";
+        let old = "- id: arduino:avr\n  installed: 1.8.5\n  latest: 1.8.6\n";
+        assert_eq!(
+            parse_core_list(new),
+            Some(vec![("arduino:avr".to_string(), "1.8.6".to_string())])
+        );
+        assert_eq!(
+            parse_core_list(old),
+            Some(vec![("arduino:avr".to_string(), "1.8.5".to_string())])
+        );
+        assert_eq!(parse_core_list("platforms: []\n"), Some(vec![]));
+    }
+
+    #[test]
+    fn gc_keeps_installed_platforms_and_used_objects() {
+        let root = tempfile::tempdir().unwrap();
+        let lib = root.path().join("arduino-lib");
+        let kept = lib.join("arduino/avr/1.8.6/cores/arduino/uno/standard");
+        let old = lib.join("arduino/avr/1.8.5/cores/arduino/uno/standard");
+        let other = lib.join("esp32/esp32/2.0.0/cores/esp32/esp32/esp32");
+        for d in [&kept, &old, &other] {
+            std::fs::create_dir_all(d).unwrap();
+        }
+        let installed = vec![("arduino:avr".to_string(), "1.8.6".to_string())];
+        assert_eq!(
+            stale_platform_dirs(&lib, &installed),
+            vec![lib.join("arduino/avr/1.8.5"), lib.join("esp32/esp32/2.0.0")]
+        );
+
+        let objects = lib.join("objects");
+        std::fs::create_dir_all(&objects).unwrap();
+        let header = root.path().join("a.h");
+        std::fs::write(&header, "int a;").unwrap();
+        let manifest = object_cache::deps_manifest([header.to_owned()].iter()).unwrap();
+        std::fs::write(objects.join("a-1.o"), "").unwrap();
+        std::fs::write(objects.join("a-1.deps"), &manifest).unwrap();
+        std::fs::write(objects.join("b-2.o"), "").unwrap();
+        std::fs::write(objects.join("b-2.deps"), &manifest).unwrap();
+        // the header changed after `b-3` was cached
+        std::fs::write(objects.join("b-3.o"), "").unwrap();
+        std::fs::write(objects.join("b-3.deps"), &manifest).unwrap();
+        std::fs::write(&header, "int a; int b;").unwrap();
+        let manifest = object_cache::deps_manifest([header.to_owned()].iter()).unwrap();
+        std::fs::write(objects.join("a-1.deps"), &manifest).unwrap();
+        std::fs::write(objects.join("b-2.deps"), &manifest).unwrap();
+        let a_used = SystemTime::now() - Duration::from_secs(10 * 24 * 60 * 60);
+        std::fs::File::options()
+            .append(true)
+            .open(objects.join("a-1.o"))
+            .unwrap()
+            .set_modified(a_used)
+            .unwrap();
+
+        let since = SystemTime::now() - Duration::from_secs(5 * 24 * 60 * 60);
+        assert_eq!(
+            stale_objects(&objects, since),
+            vec![
+                objects.join("a-1.o"),
+                objects.join("a-1.deps"),
+                objects.join("b-3.o"),
+                objects.join("b-3.deps"),
+            ]
+        );
+    }
+}
@@ -1,12 +1,8 @@
-#[macro_use]
-extern crate lazy_static;    
-
 pub fn main_entry() {
     if let Some(info) = arduino_cli_util::BUILD_PROPERTIES.as_ref() {
         let c = compile_bindgen::CompileFactory::new(info);
 
-        let out_lib_dir = info.default_archive_dir();
-        c.compile(Some(out_lib_dir));
+        c.compile(None);
 
         #[cfg(feature = "native_bindgen")]
         {
@@ -100,15 +96,19 @@ mod clang_x {
     }
 }
 
-mod compile_bindgen {
+pub(crate) mod compile_bindgen {
 
     use super::arduino_cli_util;
     #[cfg(feature = "prettify_bindgen")]
     use super::clang_x;
+    use super::{core_bundle, object_cache};
+    #[cfg(feature = "native_bindgen")]
+    use std::io::Write;
     use std::{
-        io::Write,
+        cell::RefCell,
+        collections::BTreeSet,
+        hash::Hasher,
         path::{Path, PathBuf},
-        process::Stdio,
     };
     use tempfile::tempdir;
 
@@ -149,7 +149,7 @@ mod compile_bindgen {
                 incs.extend(p.inc_dirs);
             }
             incs.extend(info.core_incs());
-            incs.extend(info.get_external_libraries_path());
+            incs.extend(info.libraries_incs());
 
             clang_args.extend(incs.iter().map(|t| format!("-I{}", t)).collect::<Vec<_>>());
 
@@ -300,245 +300,304 @@ mod compile_bindgen {
     #[must_use]
     pub struct CompileFactory<'a> {
         info: &'a arduino_cli_util::Info,
+        /// inputs reported to cargo by `cargo:rerun-if-changed`
+        rerun_if_changed: RefCell<BTreeSet<PathBuf>>,
+        /// every C/C++/asm compile, cached or not, for compile_commands.json
+        compile_commands: RefCell<BTreeSet<CompileCommand>>,
+        /// link lines and hooks, as they are emitted
+        report: RefCell<BuildReport>,
+        standalone: Option<Standalone>,
+    }
+
+    /// how a [`CompileFactory`] compiles outside of a build script, e.g. for tools and tests.
+    /// nothing is read from the cargo env vars, and no `cargo:` directive is printed.
+    #[derive(Debug, Clone)]
+    pub struct Standalone {
+        /// objects, archives, compile_commands.json and the build report go here
+        pub out_dir: PathBuf,
+        /// rust target triple cc-rs derives its default flags from, e.g. `avr-none`
+        pub target: String,
+        pub host: String,
+        /// `0`-`3`, `s` or `z`
+        pub opt_level: String,
+        /// debug info, like the `debug` of a cargo profile
+        pub debug: bool,
     }
 
     impl<'a> CompileFactory<'a> {
         pub fn new(info: &'a arduino_cli_util::Info) -> Self {
-            Self { info }
+            Self {
+                info,
+                rerun_if_changed: RefCell::new(BTreeSet::new()),
+                compile_commands: RefCell::new(BTreeSet::new()),
+                report: RefCell::new(BuildReport::default()),
+                standalone: None,
+            }
         }
 
-        pub fn compile(&self, out_lib_dir: Option<PathBuf>) {
-            self.prebuild();
-            self.prelink();
+        /// a factory that compiles into `options.out_dir`, see [`Standalone`].
+        /// the core archive goes to `<out_dir>/core` unless [`Self::compile`] is given a dir.
+        #[allow(dead_code)] // the build script itself never runs standalone
+        pub fn standalone(info: &'a arduino_cli_util::Info, options: Standalone) -> Self {
+            Self {
+                standalone: Some(options),
+                ..Self::new(info)
+            }
+        }
 
-            println!("cargo:rerun-if-env-changed=ARDUINO_SDK_CONFIG");
+        /// `out_lib_dir` is where libarduino_core.a is cached, by default [`Self::core_archive_dir`].
+        /// the library archives always go to OUT_DIR, or to the dir of a [`Standalone`] factory.
+        pub fn compile(&self, out_lib_dir: Option<PathBuf>) {
+            if self.standalone.is_none() {
+                self.check_target();
+            }
+            self.run_hooks("prebuild");
 
-            const CORE_NAME: &str = "arduino_core";
-            const EXTERNAL_NAME: &str = "arduino_external";
+            self.cargo("rerun-if-env-changed=ARDUINO_SDK_CONFIG");
+            self.cargo("rerun-if-env-changed=ARDUINO_SDK_CACHE_DIR");
+            for w in self.info.warnings() {
+                self.cargo(format!("warning=: {}", w));
+            }
+            for p in self.info.unmatched_sources_patterns() {
+                self.cargo(format!("warning=: the \"sources\" pattern {} matches no C, C++ or asm file", p.to_string_lossy()));
+            }
 
-            //if it is not called in build script, use tempdir
-            let out_dir_env = std::env::var("OUT_DIR");
-            let obj_out_dir = if out_dir_env.is_ok() {
-                None
-            } else {
-                Some(tempdir().unwrap())
-            };
+            let _ = std::fs::create_dir_all(self.out_dir());
+            let obj_out_dir = self.obj_out_dir();
 
-            let out_lib_dir = if let Some(p) = out_lib_dir {
-                p
-            } else {
-                self.info.default_archive_dir()
+            let out_lib_dir = match (out_lib_dir, &self.standalone) {
+                (Some(p), _) => p,
+                (None, Some(s)) => s.out_dir.join("core"),
+                (None, None) => self.core_archive_dir(),
             };
-
-            let static_core_lib_path = out_lib_dir.join(format!("lib{}.a", CORE_NAME));
-
-            if !static_core_lib_path.exists() {
-                let core_srcs =
-                    self.compile_core_(&obj_out_dir, &Some(out_lib_dir.to_owned()), CORE_NAME);
-
-                if core_srcs.len() > 0 {
-                    let core_lib_check_path =
-                        out_lib_dir.join(format!("lib{}.a_srcs.txt", CORE_NAME));
-                    if let Ok(mut file) = std::fs::File::create(core_lib_check_path) {
-                        let _ = file.write_all(
-                            core_srcs
-                                .iter()
-                                .map(|p| {
-                                    let p2: PathBuf =
-                                        p.iter().skip_while(|s| *s != "packages").collect();
-                                    p2
-                                })
-                                .map(|s| s.to_string_lossy().to_string())
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                                .as_bytes(),
-                        );
+            let static_core_lib_path = format!("lib{}.a", CORE_NAME);
+            let core_deps_path = format!("lib{}.a.deps", CORE_NAME);
+
+            // external libraries belong to the down-stream app, they never go to the shared cache
+            let external_lib_dir = self.out_dir();
+            // each unit is compiled between its own hooks, in the order of the arduino builder
+            self.run_hooks("sketch.prebuild");
+            let mut externals = self.compile_external_(&obj_out_dir, &Some(external_lib_dir.to_owned()), true);
+            self.run_hooks("sketch.postbuild");
+            self.run_hooks("libraries.prebuild");
+            externals.extend(self.compile_external_(&obj_out_dir, &Some(external_lib_dir.to_owned()), false));
+            self.run_hooks("libraries.postbuild");
+            self.run_hooks("core.prebuild");
+            let (core_dir, core_srcs) = self.compile_core(&out_lib_dir);
+            self.run_hooks("core.postbuild");
+            let core_archive = core_dir.join(&static_core_lib_path);
+
+            let combine = self.combine_link_directives(&core_archive);
+            // static libraries are searched in order, so a library must come before the
+            // libraries it uses, and the core comes last
+            if externals.len() > 0 {
+                self.cargo_link("rustc-link-search", external_lib_dir.to_string_lossy());
+            }
+            if externals.iter().any(|(n, _)| n == SKETCH_NAME) {
+                self.cargo_link("rustc-link-lib", format!("static:-bundle,+whole-archive={}", SKETCH_NAME));
+            }
+            for (library, name) in self.info.libraries().iter().zip(external_archive_names(self.info.libraries())) {
+                if externals.iter().any(|(n, _)| *n == name) {
+                    if library.dot_a_linkage() {
+                        self.cargo_link("rustc-link-lib", format!("static={}", name));
+                    } else {
+                        // the Arduino builder links the objects themselves, so static constructors
+                        // and ISRs are kept even if no symbol of theirs is referenced
+                        self.cargo_link("rustc-link-lib", format!("static:-bundle,+whole-archive={}", name));
                     }
                 }
-            }
-            println!("cargo:rustc-link-search={}", out_lib_dir.to_string_lossy());
-            // if static_core_lib_path.exists() {
-            println!("cargo:rustc-link-lib=static={}", CORE_NAME);
-            // }
-
-            let external_lib_path = out_lib_dir.join(format!("lib{}.a", EXTERNAL_NAME));
-            let external_lib_check_path =
-                out_lib_dir.join(format!("lib{}.a_srcs.txt", EXTERNAL_NAME));
-            let external_srcs =
-                self.compile_external_(&obj_out_dir, &Some(out_lib_dir), EXTERNAL_NAME);
-            if external_lib_path.exists() {
-                println!("cargo:rustc-link-lib=static={}", EXTERNAL_NAME);
-            }
-
-            if external_srcs.len() > 0 {
-                if let Ok(mut file) = std::fs::File::create(external_lib_check_path) {
-                    let _ = file.write_all(
-                        external_srcs
-                            .iter()
-                            .map(|p| {
-                                let p2: PathBuf =
-                                    p.iter().skip_while(|s| *s != "libraries").collect();
-                                p2
-                            })
-                            .map(|s| s.to_string_lossy().to_string())
-                            .collect::<Vec<_>>()
-                            .join("\n")
-                            .as_bytes(),
-                    );
+                let precompiled = self.info.precompiled_dir(library);
+                if precompiled.is_none() && library.get_property("precompiled").is_some_and(|p| p == "true" || p == "full") {
+                    self.cargo(format!(
+                        "warning=: library '{}' is precompiled, but has no archive for {}",
+                        library.name,
+                        self.info.get_var("build.mcu").unwrap_or_default()
+                    ));
+                }
+                if let Some(dir) = precompiled {
+                    self.cargo_link("rustc-link-search", dir.to_string_lossy());
+                    for a in files_in_folder(&dir.to_string_lossy(), "*.a") {
+                        let file_name = a.file_name().unwrap().to_string_lossy().to_string();
+                        self.cargo_link("rustc-link-lib", static_lib_of(&file_name));
+                    }
+                }
+                for (key, value) in ldflag_directives(&library.ldflags()) {
+                    self.cargo_link(key, value);
                 }
             }
-            // #[cfg(esp8266_esp8266)]
-            self.external_link();
-
-
-        }
-        fn external_link(&self) {
+            // in a `-Wl,--start-group` of the combine pattern the core is linked by its path, with
+            // the libraries it resolves symbols against
+            let core_in_group = combine.iter().any(|(_, v)| Path::new(v) == core_archive);
+            if core_in_group == false {
+                self.cargo_link("rustc-link-search", core_dir.to_string_lossy());
+                self.cargo_link("rustc-link-lib", format!("static={}", CORE_NAME));
+            }
 
-            if let Some(pat) = self.info.get_pat("recipe.c.combine.pattern") {
+            if let Ok(s) = std::fs::read_to_string(core_dir.join(&core_deps_path)) {
+                self.track(object_cache::read_deps_manifest(&s));
+            }
+            self.track(self.info.config_inputs());
+            for p in self.rerun_if_changed.borrow().iter() {
+                self.cargo(format!("rerun-if-changed={}", p.to_string_lossy()));
+            }
+            self.write_compile_commands(&self.out_dir());
 
-                let path_removable = |s: &str| s.contains("{") || s.contains("}");
-                let lib_removable = |s: &str| {
-                    let t = s.trim();
-                    t == "m" || t == "gcc"// || t == "c"
-                };
+            // #[cfg(esp8266_esp8266)]
+            for (key, value) in combine {
+                self.cargo_link(key, value);
+            }
+            self.export_link_args();
+            // the last stage before linking the build script sees. cargo may compile the crates
+            // that depend on this one before the firmware is linked
+            self.run_hooks("linking.prelink");
+
+            let mut archives = vec![ArchiveReport {
+                name: CORE_NAME.to_string(),
+                path: core_archive,
+                sources: core_srcs,
+            }];
+            for (name, srcs) in externals {
+                archives.push(ArchiveReport {
+                    path: external_lib_dir.join(format!("lib{}.a", name)),
+                    name,
+                    sources: srcs,
+                });
+            }
+            self.write_report(archives, &self.out_dir());
 
-                for lp in pat
-                    .flags
-                    .iter()
-                    .filter_map(|s| s.trim().strip_prefix("-L"))
-                    .filter(|s| path_removable(s) == false)
-                    .map(|s| PathBuf::from(s))
-                    .collect::<Vec<_>>()
-                {
-                    println!("cargo:rustc-link-search={}", lp.to_string_lossy());
-                }
 
-                for lib in pat
-                    .flags
-                    .iter()
-                    .filter_map(|s| s.trim().strip_prefix("-l"))
-                   .filter(|s| lib_removable(s) == false)
-                    .collect::<Vec<_>>()
-                {
-                    println!("cargo:rustc-link-lib={}", lib);
-                }
+        }
+        /// fail early if the rust target compiles for another MCU than the board, see [`check_target_mcu`]
+        fn check_target(&self) {
+            let target = std::env::var("TARGET").unwrap_or_default();
+            let rustflags = std::env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+            let rustflags = rustflags.split('\x1f').map(str::to_string).collect::<Vec<_>>();
+            let spec = find_target_spec(&target, &self.info.source_dir()).and_then(|p| {
+                self.track([p.to_owned()]);
+                std::fs::read_to_string(p).ok()
+            });
+            match check_target_mcu(self.info, &target, &rustflags, spec.as_deref()) {
+                Ok(Some(w)) => self.cargo(format!("warning=: {}", w)),
+                Ok(None) => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
 
+        /// the linker flags of `recipe.c.combine.pattern` for cargo, see [`link_directives`].
+        /// `{build.path}` is OUT_DIR, like for the hooks, and `{archive_file_path}` `core_archive`.
+        fn combine_link_directives(&self, core_archive: &Path) -> Vec<(&'static str, String)> {
+            let Some(pat) = self.info.get_pat("recipe.c.combine.pattern") else {
+                return vec![];
+            };
+            let mut values = hook_values(self.info, &self.out_dir(), &self.info.project_name());
+            values.push(("archive_file_path".to_string(), core_archive.to_string_lossy().to_string()));
+            let (directives, unresolved) = link_directives(self.info, &pat.flags, &values, &self.info.link_arg_policy());
+            for a in unresolved {
+                self.cargo(format!(
+                    "warning=: '{}' of recipe.c.combine.pattern has an unknown placeholder, it is not passed to the linker",
+                    a
+                ));
             }
+            directives
         }
 
-        /// compile and got objects. include core and core iteself libraries.
-        /// suggest in build script, set obj_out_dir/lib_out_dir to NONE, then it will be automaticaly setted to OUT_DIR env
-        fn compile_core_<P1: AsRef<Path>, P2: AsRef<Path>>(
+        /// the configured builders for the core, one per recipe, with the files they compile.
+        /// the base builder is returned first, it is also the archiver.
+        fn core_builds<P1: AsRef<Path>>(
             &self,
             obj_out_dir: &Option<P1>,
-            lib_out_dir: &Option<P2>,
-            name: &str,
-        ) -> Vec<PathBuf> {
-            let mut out_objects = Vec::<PathBuf>::new();
-            let mut srcs = Vec::<PathBuf>::new();
-
+        ) -> (cc::Build, Vec<RecipeBuild>) {
             // try_compile_intermediates
-            let mut builder = cc::Build::new();
+            let mut builder = self.base_builder();
             if let Some(p) = obj_out_dir {
                 builder.out_dir(p);
             }
 
-            //if it is not called in build script, use temp data to avoid cc-rs requirement
-            if std::env::var("OUT_DIR").is_err() {
-                let fqbn = self.info.get_fqbn();
-                let x = fqbn.splitn(4, ":").collect::<Vec<_>>();
-                let (_packager, arch, _boardid) = (x[0], x[1], x[2]);
-
-                builder
-                    .target(arch)
-                    .opt_level_str("s")
-                    .host("x86_64-pc-windows-msvc");
-            }
-
             for p in self.info.core_incs() {
                 builder.include(p);
             }
+            // dependency files, to know when the cached archive is outdated
+            builder.flag("-MMD");
 
             if let Some(p) = self.info.get_pat(arduino_cli_util::PRIVATE_CORE_DEDICATED) {
                 p.inc_dirs.iter().for_each(|i| {
                     builder.include(i);
                 });
+                // cc passes the flags to .S files too
                 p.flags.iter().for_each(|i| {
-                    builder.asm_flag(i);
                     builder.flag(i);
                 });
             }
 
-            //s
-            if let (Some(p), files) = (
-                self.info.get_pat("recipe.S.o.pattern"),
-                self.core_project_files("*.S"),
-            ) {
-                srcs.extend(files.to_owned());
-                if files.len() > 0 {
-                    println!("cargo:warning=: core asm lib not yet built', building now");
-                    let mut b = builder.clone();
+            let builds = self.recipe_builds(&builder, |ext| self.core_project_files(&format!("*.{}", ext)));
+            (builder, builds)
+        }
 
-                    b.compiler(&p.cmd);
-                    p.inc_dirs.iter().for_each(|i| {
-                        b.include(i);
-                    });
-                    p.flags.iter().for_each(|i| {
-                        b.asm_flag(i);
-                    });
-                    files.iter().for_each(|i| {
-                        b.file(i);
-                    });
-                    out_objects.extend(b.compile_intermediates());
-                }
-            }
-            //c
-            if let (Some(p), files) = (
-                self.info.get_pat("recipe.c.o.pattern"),
-                self.core_project_files("*.c"),
-            ) {
-                srcs.extend(files.to_owned());
-                if files.len() > 0 {
-                    println!("cargo:warning=: core c lib not yet built', building now");
+        /// one builder per platform recipe (.S, .c and .cpp), based on `builder`, for the files
+        /// `files_of(extension)` returns. recipes without files are left out.
+        fn recipe_builds(
+            &self,
+            builder: &cc::Build,
+            files_of: impl Fn(&str) -> Vec<PathBuf>,
+        ) -> Vec<RecipeBuild> {
+            let maps = self.info.prefix_maps(&self.out_dir());
+            let mut builds = vec![];
+            for (kind, recipe, ext) in [
+                ("asm", "recipe.S.o.pattern", "S"),
+                ("c", "recipe.c.o.pattern", "c"),
+                ("cpp", "recipe.cpp.o.pattern", "cpp"),
+            ] {
+                if let (Some(p), files) = (self.info.get_pat(recipe), files_of(ext)) {
+                    if files.len() == 0 {
+                        continue;
+                    }
                     let mut b = builder.clone();
 
                     b.compiler(&p.cmd);
                     p.inc_dirs.iter().for_each(|i| {
                         b.include(i);
                     });
+                    // for .S files too, so the flags stay visible to `get_compiler`
                     p.flags.iter().for_each(|i| {
                         b.flag(i);
                     });
-                    files.iter().for_each(|i| {
-                        b.file(i);
-                    });
-                    out_objects.extend(b.compile_intermediates());
+                    add_prefix_maps(&mut b, &maps);
+                    builds.push((kind, b, files));
                 }
             }
+            builds
+        }
 
-            //cpp
-            if let (Some(p), files) = (
-                self.info.get_pat("recipe.cpp.o.pattern"),
-                self.core_project_files("*.cpp"),
-            ) {
-                srcs.extend(files.to_owned());
-                if files.len() > 0 {
-                    println!("cargo:warning=: core cpp lib not yet built', building now");
-                    let mut b = builder.clone();
-
-                    b.compiler(&p.cmd);
-                    p.inc_dirs.iter().for_each(|i| {
-                        b.include(i);
-                    });
-                    p.flags.iter().for_each(|i| {
-                        b.flag(i);
-                    });
-                    files.iter().for_each(|i| {
-                        b.file(i);
-                    });
-                    out_objects.extend(b.compile_intermediates());
+        /// compile and got objects. include core and core iteself libraries.
+        /// suggest in build script, set obj_out_dir/lib_out_dir to NONE, then it will be automaticaly setted to OUT_DIR env
+        /// false if the dependency manifest next to the archive could not be written, the archive
+        /// must not be cached then, see [`core_ready`].
+        fn compile_core_<P1: AsRef<Path>, P2: AsRef<Path>>(
+            &self,
+            obj_out_dir: &Option<P1>,
+            lib_out_dir: &Option<P2>,
+            name: &str,
+        ) -> bool {
+            // (source, object)
+            let mut out_objects = Vec::<(PathBuf, PathBuf)>::new();
+
+            let (mut builder, builds) = self.core_builds(obj_out_dir);
+            let mut jobs = vec![];
+            for (kind, b, files) in builds {
+                self.cargo(format!("warning=: core {} lib not yet built, building now", kind));
+                jobs.push((b, files));
+            }
+            let mut failures = vec![];
+            for (r, (_, files)) in compile_parallel(&jobs).into_iter().zip(&jobs) {
+                match r {
+                    Ok(o) => out_objects.extend(files.iter().cloned().zip(o)),
+                    Err(e) => failures.push(e),
                 }
             }
+            if failures.len() > 0 {
+                self.report_failures(&failures);
+                panic!("core:\n{}", failures.join("\n"));
+            }
             if out_objects.len() > 0 {
                 let ar_cmd = self.info.get_ar_cmd().unwrap();
                 builder.archiver(ar_cmd.as_str());
@@ -548,117 +607,156 @@ mod compile_bindgen {
                     }
                     builder.out_dir(out);
                 }
-                out_objects.iter().for_each(|t| {
-                    builder.object(t);
-                });
-                builder.compile(name);
+                let obj_root = match obj_out_dir {
+                    Some(p) => p.as_ref().to_owned(),
+                    None => self.out_dir(),
+                };
+                for m in stable_members(&out_objects, &obj_root.join("members").join(name)) {
+                    builder.object(m);
+                }
+                // lib_out_dir is a temp dir that is renamed later, `compile` prints the final link-search
+                builder.cargo_metadata(false);
+                // `compile` would exit the process on an archiver error
+                if let Err(e) = builder.try_compile(name) {
+                    panic!("{}: {}", name, e);
+                }
+
+                let deps = out_objects
+                    .iter()
+                    .flat_map(|(_, o)| object_cache::object_deps(o))
+                    .collect::<BTreeSet<_>>();
+                // also when the manifest below is missing, `compile` reads the headers from it
+                self.track(deps.iter().cloned());
+                if let Some(out) = lib_out_dir {
+                    let path = out.as_ref().join(format!("lib{}.a.deps", name));
+                    let written = match object_cache::deps_manifest(deps.iter()) {
+                        Some(manifest) => std::fs::write(&path, manifest).map_err(|e| e.to_string()),
+                        None => Err("a source or header of the core is not readable".to_string()),
+                    };
+                    if let Err(e) = written {
+                        self.cargo(format!("warning=: {}: {}, the core is not cached", path.to_string_lossy(), e));
+                        return false;
+                    }
+                }
             }
-            srcs.sort();
-            srcs
+            true
         }
-        /// platform's itself  core + variant + libraries
+        // platform's itself  core + variant + libraries
 
         /// compile external libraries ,that located in user directory (sketchbook).
+        /// each library goes to its own `libarduino_<library>.a`, the down-stream "sources"
+        /// go to `libarduino_sketch.a`. only the sketch with `sketch`, otherwise only the libraries.
+        /// return (archive name, sources) of the produced archives, in link order.
         /// suggest in build script, set obj_out_dir/lib_out_dir to NONE, then it will be automaticaly setted to OUT_DIR env
         fn compile_external_<P1: AsRef<Path>, P2: AsRef<Path>>(
             &self,
             obj_out_dir: &Option<P1>,
             lib_out_dir: &Option<P2>,
-            name: &str,
-        ) -> Vec<PathBuf> {
-            let mut builder = cc::Build::new();
+            sketch: bool,
+        ) -> Vec<(String, Vec<PathBuf>)> {
+            let mut builder = self.base_builder();
 
             for p in self.info.core_incs() {
                 builder.include(p);
             }
-            for p in self.info.get_external_libraries_path() {
+            for p in self.info.libraries_incs() {
                 builder.include(p);
             }
-            if let Some(p) = obj_out_dir {
-                builder.out_dir(p);
-            }
+            let obj_root = match obj_out_dir {
+                Some(p) => p.as_ref().to_owned(),
+                None => self.out_dir(),
+            };
 
-            //if it is not called in build script, use temp data to avoid cc-rs requirement
-            if std::env::var("OUT_DIR").is_err() {
-                let fqbn = self.info.get_fqbn();
-                let x = fqbn.splitn(4, ":").collect::<Vec<_>>();
-                let (_packager, arch, _boardid) = (x[0], x[1], x[2]);
+            let mut result = Vec::<(String, Vec<PathBuf>)>::new();
+            let cache = object_cache::ObjectCache::new(self.info.object_cache_dir());
 
-                builder
-                    .target(arch)
-                    .opt_level_str("s")
-                    .host("x86_64-pc-windows-msvc");
-            }
-
-            let mut out_objects = Vec::<PathBuf>::new();
-            let mut srcs = Vec::<PathBuf>::new();
-
-            //c
-            if let (Some(p), files) = (
-                self.info.get_pat("recipe.c.o.pattern"),
-                self.external_libraries_project_files("*.c"),
-            ) {
-                srcs.extend(files.to_owned());
-                if files.len() > 0 {
-                    println!("cargo:warning=: external c lib not yet built', building now");
-                    let mut b = builder.clone();
+            // (archive name, sources, allow_failures), the down-stream shims first, like the sketch
+            // of the arduino builder
+            let arch = self.info.get_arch();
+            let mut units = Vec::<(String, Vec<PathBuf>, bool)>::new();
+            let shims = self.info.get_sketch_sources();
+            if sketch && shims.len() > 0 {
+                units.push((SKETCH_NAME.to_string(), shims, false));
+            }
+            let libraries = self.info.libraries().iter().zip(external_archive_names(self.info.libraries()));
+            for (library, name) in libraries.filter(|_| sketch == false) {
+                if self.info.compile_sources_of(library) {
+                    let files = ["S", "c", "cpp"]
+                        .iter()
+                        .flat_map(|e| library.source_files(e, &arch))
+                        .collect::<Vec<_>>();
+                    units.push((
+                        name,
+                        files,
+                        self.info.allows_failures(library),
+                    ));
+                }
+            }
 
-                    b.compiler(&p.cmd);
-                    p.inc_dirs.iter().for_each(|i| {
-                        b.include(i);
-                    });
-                    p.flags.iter().for_each(|i| {
-                        b.flag(i);
-                    });
-                    files.iter().for_each(|i| {
-                        println!("cargo:rerun-if-changed={}", i.to_string_lossy());
-                        b.file(i);
-                    });
-                    if let Ok(v) = b.try_compile_intermediates() {
-                        out_objects.extend(v);
+            // all recipes of all units are planned first, then the misses are compiled concurrently
+            let mut planned = vec![];
+            for (name, unit_files, allow_failures) in units {
+                // separate object dirs, so `utility/twi.c` of two libraries can not collide
+                let mut builder = builder.clone();
+                builder.out_dir(obj_root.join("external").join(&name));
+                let builds = self.recipe_builds(&builder, |ext| {
+                    unit_files
+                        .iter()
+                        .filter(|f| f.extension().is_some_and(|e| e == ext))
+                        .cloned()
+                        .collect::<Vec<_>>()
+                });
+                planned.push((name, allow_failures, builder, builds));
+            }
+            let jobs = planned
+                .iter()
+                .flat_map(|(_, _, _, builds)| builds.iter().map(|(kind, b, files)| (*kind, b.clone(), files.to_owned())))
+                .collect::<Vec<_>>();
+            let mut compiled = self.compile_all_cached(jobs, &cache).into_iter();
+
+            for (name, allow_failures, mut builder, builds) in planned {
+                // (source, object)
+                let mut out_objects = Vec::<(PathBuf, PathBuf)>::new();
+                let mut failures = Vec::<String>::new();
+                let mut srcs = Vec::<PathBuf>::new();
+                for (_, _, files) in builds {
+                    srcs.extend(files.to_owned());
+                    match compiled.next().unwrap() {
+                        Ok(o) => out_objects.extend(files.into_iter().zip(o)),
+                        Err(e) => failures.push(e),
                     }
                 }
-            }
-            //cpp
-            if let (Some(p), files) = (
-                self.info.get_pat("recipe.cpp.o.pattern"),
-                self.external_libraries_project_files("*.cpp"),
-            ) {
-                srcs.extend(files.to_owned());
-                if files.len() > 0 {
-                    println!("cargo:warning=: external cpp lib not yet built', building now",);
-                    let mut b = builder.clone();
 
-                    b.compiler(&p.cmd);
-                    p.inc_dirs.iter().for_each(|i| {
-                        b.include(i);
-                    });
-                    p.flags.iter().for_each(|i| {
-                        b.flag(i);
-                    });
-                    files.iter().for_each(|i| {
-                        println!("cargo:rerun-if-changed={}", i.to_string_lossy());
-                        b.file(i);
-                    });
-                    if let Ok(v) = b.try_compile_intermediates() {
-                        out_objects.extend(v);
+                if failures.len() > 0 {
+                    self.report_failures(&failures);
+                    let report = format!("{}:\n{}", name, failures.join("\n"));
+                    if allow_failures {
+                        self.cargo(format!("warning=: allow_failures, {} is not linked", name));
+                        continue;
                     }
+                    panic!("{}", report);
                 }
-            }
 
-            if out_objects.len() > 0 {
-                let ar_cmd = self.info.get_ar_cmd().unwrap();
-                builder.archiver(ar_cmd.as_str());
-                if let Some(out) = lib_out_dir {
-                    builder.out_dir(out);
+                if out_objects.len() > 0 {
+                    let ar_cmd = self.info.get_ar_cmd().unwrap();
+                    builder.archiver(ar_cmd.as_str());
+                    if let Some(out) = lib_out_dir {
+                        builder.out_dir(out);
+                    }
+                    for m in stable_members(&out_objects, &obj_root.join("members").join(&name)) {
+                        builder.object(m);
+                    }
+                    // `compile` prints the link-lib lines itself, in link order
+                    builder.cargo_metadata(false);
+                    if let Err(e) = builder.try_compile(&name) {
+                        panic!("{}: {}", name, e);
+                    }
+
+                    srcs.sort();
+                    result.push((name, srcs));
                 }
-                out_objects.iter().for_each(|t| {
-                    builder.object(t);
-                });
-                builder.compile(name);
             }
-            srcs.sort();
-            srcs
+            result
         }
 
         fn core_project_files(&self, patten: &str) -> Vec<PathBuf> {
@@ -672,134 +770,1109 @@ mod compile_bindgen {
                 result.extend(s);
             }
 
-            let pattern = format!("**/{}", patten);
-            for library in self.info.get_arduino_libraries_path() {
-                let lib_sources = files_in_folder(library.as_str(), &pattern);
-                result.extend(lib_sources);
-            }
-
             result
         }
 
-        fn external_libraries_project_files(&self, patten: &str) -> Vec<PathBuf> {
-            let mut result = Vec::<PathBuf>::new();
+        /// compile the `jobs` (kind, configured builder, files), reusing objects from `cache`.
+        /// only the files without a valid cache entry are handed to the compiler, all jobs at
+        /// once, see [`compile_parallel`].
+        /// return the cached objects of each job in the order of its files, in the order of `jobs`.
+        fn compile_all_cached(
+            &self,
+            jobs: Vec<RecipeBuild>,
+            cache: &object_cache::ObjectCache,
+        ) -> Vec<Result<Vec<PathBuf>, String>> {
+            // (keys, objects, indices of the misses) of each job
+            let mut plans = vec![];
+            let mut misses = vec![];
+            for (kind, b, files) in &jobs {
+                let mut b = b.clone();
+                // the header dependencies are needed to validate the cache entries
+                let tool = b.get_compiler();
+                if tool.args().iter().all(|a| a != "-MMD") {
+                    b.flag("-MMD");
+                }
+                self.record_commands(&b, files);
+                let tool = b.get_compiler();
+                // the prefix maps only rename paths, leave them out so projects (OUT_DIRs) share objects
+                let args = tool
+                    .args()
+                    .iter()
+                    .map(|a| a.to_string_lossy().to_string())
+                    .filter(|a| !a.starts_with("-ffile-prefix-map=") && !a.starts_with("-fdebug-prefix-map="))
+                    .collect::<Vec<_>>();
+
+                let keys = files
+                    .iter()
+                    .map(|f| object_cache::ObjectCache::key(tool.path(), &args, f))
+                    .collect::<Vec<_>>();
+                let objects = keys
+                    .iter()
+                    .map(|k| k.as_ref().and_then(|k| cache.lookup(k)))
+                    .collect::<Vec<_>>();
+
+                let missing = (0..files.len())
+                    .filter(|i| objects[*i].is_none())
+                    .collect::<Vec<_>>();
+                if missing.len() > 0 {
+                    self.cargo(format!(
+                        "warning=: external {} lib not yet built, building {} of {} files now",
+                        kind,
+                        missing.len(),
+                        files.len()
+                    ));
+                    let missing_files = missing.iter().map(|i| files[*i].to_owned()).collect::<Vec<_>>();
+                    misses.push((b, missing_files));
+                }
+                self.track(files.iter().cloned());
+                for k in keys.iter().flatten() {
+                    self.track(cache.headers(k));
+                }
+                plans.push((keys, objects, missing));
+            }
 
-            let pattern = format!("**/{}", patten);
-            for l in self.info.get_external_libraries_path() {
-                let lib_sources = files_in_folder(l.as_str(), &pattern);
-                result.extend(lib_sources);
+            let mut compiled = compile_parallel(&misses).into_iter();
+            let mut result = vec![];
+            for (keys, mut objects, missing) in plans {
+                if missing.len() > 0 {
+                    let v = match compiled.next().unwrap() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            result.push(Err(e));
+                            continue;
+                        }
+                    };
+                    // cc keeps the order of the input files
+                    for (i, obj) in missing.iter().zip(v) {
+                        // the headers of a fresh object, a cached one had them tracked above
+                        self.track(object_cache::object_deps(&obj));
+                        objects[*i] = match &keys[*i] {
+                            Some(k) => cache.store(k, &obj).or(Some(obj)),
+                            None => Some(obj),
+                        };
+                    }
+                }
+                result.push(Ok(objects.into_iter().flatten().collect()));
             }
             result
         }
 
-
-        fn prebuild(&self){
-            for mut cmd in self.get_hooks_cmds("prebuild"){
-                cmd.spawn().expect("fail");
+        /// make sure `out_lib_dir` holds an up to date libarduino_core.a, and compile it if it does
+        /// not. `out_lib_dir` may be shared by several builds, see [`populate_cache_dir`].
+        /// return the dir of the archive to link and the core sources. the dir is `out_lib_dir`,
+        /// unless a fresh archive could not be cached (see [`Self::compile_core_`]), then it is
+        /// `uncached-core` in OUT_DIR.
+        pub fn compile_core(&self, out_lib_dir: &Path) -> (PathBuf, Vec<PathBuf>) {
+            let obj_out_dir = self.obj_out_dir();
+            let static_core_lib_path = format!("lib{}.a", CORE_NAME);
+            let uncached = self.out_dir().join("uncached-core");
+            let _ = std::fs::remove_dir_all(&uncached);
+            let mut kept_uncached = false;
+
+            if let Some(bundle) = self.info.core_bundle_path() {
+                self.track([bundle.to_owned()]);
+                if core_ready(out_lib_dir) == false {
+                    if let Err(e) = core_bundle::import(self.info, &self.core_digest(), &bundle, out_lib_dir) {
+                        self.cargo(format!("warning=core bundle not used, compiling the core: {}", e));
+                    }
+                }
+            }
+            populate_cache_dir(
+                out_lib_dir,
+                core_ready,
+                |tmp| {
+                    let cached = self.compile_core_(&obj_out_dir, &Some(tmp.to_owned()), CORE_NAME);
+                    let archive = tmp.join(&static_core_lib_path);
+                    if cached == false && archive.exists() {
+                        // the archive is fine, only the cache entry is not. the temp dir is
+                        // removed, a copy is linked from OUT_DIR
+                        std::fs::create_dir_all(&uncached).expect("fail to create uncached-core dir");
+                        std::fs::copy(&archive, uncached.join(&static_core_lib_path))
+                            .expect("fail to copy the core archive to uncached-core");
+                        kept_uncached = true;
+                    }
+                    cached && archive.exists()
+                },
+            );
+            let core_dir = if kept_uncached {
+                uncached
+            } else if core_ready(out_lib_dir) {
+                out_lib_dir.to_owned()
+            } else {
+                panic!(
+                    "{}: no up to date {} for the core digest {}",
+                    out_lib_dir.to_string_lossy(),
+                    static_core_lib_path,
+                    self.core_digest()
+                );
+            };
+            // the core is usually taken from the cache, its commands are recorded anyway
+            let mut core_srcs = vec![];
+            for (_, b, files) in self.core_builds(&obj_out_dir).1 {
+                self.record_commands(&b, &files);
+                core_srcs.extend(files);
             }
-//         "recipe.hooks.prebuild.2.pattern": RecipePattern {
+            (core_dir, core_srcs)
         }
-        fn prelink(&self){
-            for mut cmd in self.get_hooks_cmds("linking.prelink"){
-                cmd.spawn().expect("fail");
+
+        /// digest of the effective command lines of the core: the compiler and the arguments of
+        /// each core recipe (with the flags of the down-stream config and the cc-rs defaults of the
+        /// target, opt-level and debug setting) and the archiver. the prefix maps are left out, they
+        /// name the dirs of this crate and machine, which never reach the archive.
+        pub fn core_digest(&self) -> String {
+            let mut h = object_cache::Fnv64::default();
+            for (kind, b, _) in self.core_builds(&self.obj_out_dir()).1 {
+                h.write(kind.as_bytes());
+                if let Ok(c) = b.try_get_compiler() {
+                    h.write(&[0]);
+                    h.write(c.path().to_string_lossy().as_bytes());
+                    for a in c.args().iter().map(|a| a.to_string_lossy()) {
+                        if a.starts_with("-ffile-prefix-map=") || a.starts_with("-fdebug-prefix-map=") {
+                            continue;
+                        }
+                        h.write(&[0]);
+                        h.write(a.as_bytes());
+                    }
+                }
+                h.write(&[0xff]);
             }
-            // "recipe.hooks.linking.prelink.1.pattern"
+            h.write(self.info.get_ar_cmd().unwrap_or_default().as_bytes());
+            format!("{:016x}", h.finish())
         }
 
-        // recipe.hooks.XXXXXX.NUMBER.pattern
-        fn get_hooks_cmds(&self, name:&str)->Vec<std::process::Command>{
+        /// the cache dir of the core this factory compiles: [`arduino_cli_util::Info::default_archive_dir`]
+        /// and [`Self::core_digest`]. crates that share a cache only share a core compiled the same way.
+        pub fn core_archive_dir(&self) -> PathBuf {
+            self.info.default_archive_dir().join(self.core_digest())
+        }
 
-            let pari = [
-            ("{build.project_name}",env!("CARGO_PKG_NAME").to_string()),
-            ("{build.source.path}",Path::new(env!("CARGO_MANIFEST_DIR")).join("src").to_string_lossy().to_string() ),
-            ("{build.path}",std::env::var("OUT_DIR").unwrap()),
-            ];
+        /// cc-rs puts the objects into OUT_DIR by itself
+        fn obj_out_dir(&self) -> Option<PathBuf> {
+            self.standalone.as_ref().map(|s| s.out_dir.join("obj"))
+        }
+
+        /// a `cc::Build` for this factory, see [`Standalone`]
+        fn base_builder(&self) -> cc::Build {
+            let mut builder = cc::Build::new();
+            if let Some(s) = &self.standalone {
+                builder
+                    .target(&s.target)
+                    .host(&s.host)
+                    .opt_level_str(&s.opt_level)
+                    .debug(s.debug)
+                    .out_dir(s.out_dir.join("obj"))
+                    .cargo_metadata(false)
+                    .cargo_warnings(false);
+            }
+            builder
+        }
 
-            let prefix = format!("recipe.hooks.{}.",name);
+        /// OUT_DIR, or the dir of a [`Standalone`] factory
+        fn out_dir(&self) -> PathBuf {
+            match &self.standalone {
+                Some(s) => s.out_dir.to_owned(),
+                None => arduino_cli_util::out_dir(),
+            }
+        }
 
-            let mut result = vec![];
-            for (k,_v) in &self.info.orig_properties{
-                let k = k.as_str().trim();
-                if let Some(suf) = k.strip_prefix(&prefix){
-                    if let Some(num) = suf.strip_suffix(".pattern"){
-                        result.push(num);
-                    }
-                }
+        /// print `cargo:<directive>`. a [`Standalone`] factory prints only the warnings, to stderr
+        fn cargo(&self, directive: impl std::fmt::Display) {
+            let directive = directive.to_string();
+            if self.standalone.is_none() {
+                println!("cargo:{}", directive);
+            } else if let Some(w) = directive.strip_prefix("warning=") {
+                eprintln!("warning: {}", w.trim_start_matches(": "));
             }
-            result.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        }
 
-            let mut cmds  = vec![];
-            for num in result{
-                let patstr=format!("{}{}.pattern",&prefix,&num);
-                if let Some(mut pat) =self.info.get_pat(patstr.as_str()){
-                    let mut cmd = std::process::Command::new(pat.cmd);
-                    pat.flags.iter_mut().for_each(|x| {
-                        for (s,d) in &pari{
-                            *x = x.replace(s, d.as_str());
-                        }
-                    } );
-                    cmd.args(pat.flags);
-                    cmds.push(cmd);
+        /// print the reports of [`try_compile`] as warnings, in the order of `failures`
+        fn report_failures(&self, failures: &[String]) {
+            for l in failures.iter().flat_map(|f| f.lines()) {
+                self.cargo(format!("warning={}", l));
+            }
+        }
+
+        /// print `cargo:<key>=<value>` and add it to the build report. a `rustc-link-arg` of a
+        /// library only reaches its own targets, so link args are only collected, see
+        /// [`Self::export_link_args`]
+        fn cargo_link(&self, key: &str, value: impl std::fmt::Display) {
+            let value = value.to_string();
+            let mut report = self.report.borrow_mut();
+            match key {
+                "rustc-link-search" => report.link_search.push(value.to_owned()),
+                "rustc-link-lib" => report.link_libs.push(value.to_owned()),
+                _ => {
+                    report.link_args.push(value);
+                    return;
                 }
             }
-            cmds
-//         "recipe.hooks.prebuild.2.pattern": RecipePattern {
+            self.cargo(format!("{}={}", key, value));
         }
-    }
 
-    fn files_in_folder(folder: &str, pattern: &str) -> Vec<PathBuf> {
-        let pat = format!("{}/{}", folder, pattern);
-        let mut results = vec![];
+        /// print the collected link args as `cargo:link_args=`, separated by `\x1f` like
+        /// CARGO_ENCODED_RUSTFLAGS, nothing without link args. the build script of the down-stream
+        /// crate reads them from `DEP_ARDUINO_SDK_LINK_ARGS` and prints one `cargo:rustc-link-arg=`
+        /// for each. it must forward them, cargo never passes them to the final link by itself.
+        fn export_link_args(&self) {
+            let args = self.report.borrow().link_args.join("\x1f");
+            if args.len() > 0 {
+                self.cargo(format!("{}={}", LINK_ARGS_KEY, args));
+            }
+        }
 
-        if let Ok(g) = glob::glob(&pat) {
-            for f in g.filter_map(Result::ok) {
-                if f.ends_with("main.cpp") == false {
-                    results.push(f);
-                }
+        /// run the hooks of `stage` the down-stream config allows, see [`run_platform_hooks`].
+        /// a skipped hook is a warning. `{build.path}` is OUT_DIR and
+        /// `{build.project_name}` the firmware name, see [`arduino_cli_util::Info::project_name`].
+        /// a failing hook fails the build, like in the arduino builder.
+        fn run_hooks(&self, stage: &str) {
+            let values = hook_values(self.info, &self.out_dir(), &self.info.project_name());
+            let (reports, result) = run_platform_hooks(self.info, stage, &values, &self.info.hook_policy());
+            for reason in reports.iter().filter_map(|r| r.skipped.as_ref()) {
+                self.cargo(format!("warning=: {}", reason));
+            }
+            self.report.borrow_mut().hooks.extend(reports);
+            if let Err(e) = result {
+                panic!("{}", e);
             }
         }
-        results
-    }
-    /// output c/c++ system header dirs
-    fn output_gcc_sysheader_dirs(gcc_cmd_str: &str, cpp: bool) -> Option<Vec<String>> {
-        let mut cmd = std::process::Command::new(gcc_cmd_str);
 
-        cmd.arg("-x")
-            .arg(if cpp { "c++" } else { "c" })
-            .arg("-v")
-            .arg("-")
-            .stdin(Stdio::null())
-            .stderr(Stdio::piped());
-        if let Ok(output) = cmd.output() {
-            let stderr = String::from_utf8(output.stderr).unwrap();
-            let mut dirs = vec![];
-            let (mut begin, mut end) = (false, false);
-            for l in stderr.lines() {
-                if l.contains("search starts here:") {
-                    begin = true;
-                }
-                if l.contains("End of search list") {
-                    end = true;
-                }
-                if begin == true && end == false {
-                    dirs.push(l);
+        /// write arduino_build_report.json to `out_dir`, see [`BuildReport`]
+        fn write_report(&self, archives: Vec<ArchiveReport>, out_dir: &Path) {
+            let mut report = self.report.borrow().clone();
+            report.fqbn = self.info.get_fqbn();
+            report.platform_version = self.info.get_var("version");
+            report.platform_path = self.info.get_var("runtime.platform.path").map(PathBuf::from);
+            for recipe in [
+                "recipe.S.o.pattern",
+                "recipe.c.o.pattern",
+                "recipe.cpp.o.pattern",
+                "recipe.ar.pattern",
+                "recipe.c.combine.pattern",
+            ] {
+                if let Some(p) = self.info.get_pat(recipe) {
+                    report.tools.insert(recipe.to_string(), p.cmd);
                 }
             }
-            let dest = dirs
+            report.libraries = self
+                .info
+                .libraries()
                 .iter()
-                .filter(|s| PathBuf::from(s.trim()).is_dir() == true)
-                .map(|s| s.trim().to_string())
-                .collect::<Vec<_>>();
-
-                print!("cargo:warning= got gcc inc-dirs: {:?}",&dest);
+                .zip(external_archive_names(self.info.libraries()))
+                .map(|(l, archive)| {
+                    LibraryReport {
+                        name: l.name.to_owned(),
+                        version: l.get_property("version"),
+                        location: l.location,
+                        path: l.root.to_owned(),
+                        archive: archives.iter().any(|a| a.name == archive).then_some(archive),
+                        precompiled: self.info.precompiled_dir(l),
+                    }
+                })
+                .collect();
+            report.sources = self
+                .compile_commands
+                .borrow()
+                .iter()
+                .map(|c| SourceReport {
+                    file: PathBuf::from(&c.file),
+                    compiler: c.arguments[0].to_owned(),
+                    // without the trailing `-c <file>`
+                    flags: c.arguments[1..c.arguments.len() - 2].to_vec(),
+                })
+                .collect();
+            report.archives = archives;
+
+            let path = out_dir.join("arduino_build_report.json");
+            let json = serde_json::to_string_pretty(&report).unwrap();
+            if let Err(e) = std::fs::write(&path, json) {
+                self.cargo(format!("warning=: failed to write {}: {}", path.to_string_lossy(), e));
+            }
+        }
 
-            return Some(dest);
+        /// remember the commands `b` runs for `files`, see [`Self::write_compile_commands`]
+        fn record_commands(&self, b: &cc::Build, files: &[PathBuf]) {
+            self.compile_commands
+                .borrow_mut()
+                .extend(files.iter().map(|f| CompileCommand::new(b, f)));
         }
-        None
-    }
 
-    fn cargo_fmt_file(file: impl AsRef<Path>) {
-        let file = file.as_ref();
+        /// write compile_commands.json to `out_dir`, and copy it to "compile_commands" if that is configured
+        fn write_compile_commands(&self, out_dir: &Path) {
+            let commands = self.compile_commands.borrow().iter().cloned().collect::<Vec<_>>();
+            let json = serde_json::to_string_pretty(&commands).unwrap();
+            let path = out_dir.join("compile_commands.json");
+            if let Err(e) = std::fs::write(&path, &json) {
+                self.cargo(format!("warning=: failed to write {}: {}", path.to_string_lossy(), e));
+                return;
+            }
+            if let Some(copy) = self.info.compile_commands_path() {
+                let copy = if copy.is_dir() { copy.join("compile_commands.json") } else { copy };
+                if let Err(e) = std::fs::copy(&path, &copy) {
+                    self.cargo(format!("warning=: failed to copy compile_commands.json to {}: {}", copy.to_string_lossy(), e));
+                }
+            }
+        }
+
+        /// report `paths` to cargo by `cargo:rerun-if-changed` at the end of [`Self::compile`]
+        fn track(&self, paths: impl IntoIterator<Item = PathBuf>) {
+            self.rerun_if_changed.borrow_mut().extend(paths);
+        }
+    }
+
+    /// the placeholders of a hook besides the board properties: the build dir, the firmware name,
+    /// the sketch dir and the runtime values the arduino builder sets
+    pub fn hook_values(
+        info: &arduino_cli_util::Info,
+        build_path: &Path,
+        project_name: &str,
+    ) -> Vec<(String, String)> {
+        let os = if cfg!(windows) {
+            "windows"
+        } else if cfg!(target_os = "macos") {
+            "macosx"
+        } else {
+            "linux"
+        };
+        let source = info.source_dir().to_string_lossy().to_string();
+        [
+            ("build.path", build_path.to_string_lossy().to_string()),
+            ("build.project_name", project_name.to_string()),
+            ("build.source.path", source.to_owned()),
+            ("sketch_path", source),
+            ("build.fqbn", info.get_fqbn()),
+            ("build.arch", info.get_arch().to_uppercase()),
+            ("runtime.os", os.to_string()),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+    }
+
+    /// the MCU a rust target compiles for: the last `-C target-cpu=` of the rustflags, otherwise
+    /// the "cpu" of its custom target spec `spec` (the json). the target name is no MCU, the
+    /// built-in `avr-unknown-gnu-atmega328` is used for an atmega328p as well.
+    pub fn target_mcu(rustflags: &[String], spec: Option<&str>) -> Option<String> {
+        let flag = rustflags
+            .iter()
+            .filter_map(|f| f.split_once("target-cpu=").map(|(_, cpu)| cpu.trim().to_string()))
+            .next_back();
+        flag.or_else(|| {
+            let spec = serde_json::from_str::<serde_json::Value>(spec?).ok()?;
+            spec["cpu"].as_str().map(str::to_string)
+        })
+    }
+
+    /// the custom target spec of cargo's TARGET, which is the file name of the spec without
+    /// `.json`: `<target>.json` in the dirs of RUST_TARGET_PATH, or in `avr-specs` or `dir`
+    /// itself, where `arduino-sdk target-spec --out` puts it
+    pub fn find_target_spec(target: &str, dir: &Path) -> Option<PathBuf> {
+        if target.ends_with(".json") {
+            return Some(PathBuf::from(target));
+        }
+        let mut dirs = std::env::var_os("RUST_TARGET_PATH")
+            .map(|p| std::env::split_paths(&p).collect::<Vec<_>>())
+            .unwrap_or_default();
+        dirs.extend([dir.join("avr-specs"), dir.to_owned()]);
+        dirs.into_iter().map(|d| d.join(format!("{}.json", target))).find(|p| p.is_file())
+    }
+
+    /// on avr, an error if the rust target compiles for another MCU than `build.mcu` of the board,
+    /// see [`target_mcu`]. a target without a known MCU is left to rustc, a warning is returned
+    /// when its name ends with another MCU (`avr-unknown-gnu-atmega328` for an atmega328p).
+    pub fn check_target_mcu(
+        info: &arduino_cli_util::Info,
+        target: &str,
+        rustflags: &[String],
+        spec: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        if info.get_arch() != "avr" || target.starts_with("avr") == false {
+            return Ok(None);
+        }
+        let Some(mcu) = info.get_var("build.mcu") else {
+            return Ok(None);
+        };
+        match target_mcu(rustflags, spec) {
+            Some(cpu) if cpu != mcu => Err(format!(
+                "the rust target `{}` compiles for {}, but {} has build.mcu {}. \
+                 generate a matching target with `arduino-sdk target-spec`, or set `-C target-cpu={}`",
+                target,
+                cpu,
+                info.get_fqbn(),
+                mcu,
+                mcu
+            )),
+            Some(_) => Ok(None),
+            None => {
+                let name = target.rsplit('-').next().unwrap_or_default();
+                Ok((name.starts_with("at") && name != mcu).then(|| {
+                    format!(
+                        "the rust target `{}` may compile for {}, but {} has build.mcu {}. \
+                         set `-C target-cpu={}` if the firmware does not run",
+                        target,
+                        name,
+                        info.get_fqbn(),
+                        mcu,
+                        mcu
+                    )
+                }))
+            }
+        }
+    }
+
+    /// the `rustc-link-lib` value of the static archive `file_name`: `static=foo` for `libfoo.a`,
+    /// otherwise the file name itself with the `verbatim` modifier
+    fn static_lib_of(file_name: &str) -> String {
+        match file_name.strip_prefix("lib").and_then(|n| n.strip_suffix(".a")) {
+            Some(n) => format!("static={}", n),
+            None => format!("static:+verbatim={}", file_name),
+        }
+    }
+
+    /// translate the `ldflags` of a library.properties into (`rustc-link-search` |
+    /// `rustc-link-lib` | `rustc-link-arg`, value): `-L` is a link search path, `-l` a link lib
+    /// and an absolute path of a `.a` its dir and a static lib. cargo carries these to the final
+    /// link, only the rest are link args, see [`CompileFactory::export_link_args`].
+    pub fn ldflag_directives(flags: &[String]) -> Vec<(&'static str, String)> {
+        let mut directives = vec![];
+        let mut flags = flags.iter().filter(|f| f.len() > 0);
+        while let Some(f) = flags.next() {
+            let f = match f.as_str() {
+                "-L" | "-l" => format!("{}{}", f, flags.next().map(String::as_str).unwrap_or_default()),
+                _ => f.to_owned(),
+            };
+            let archive = Path::new(&f);
+            let directive = if let Some(dir) = f.strip_prefix("-L") {
+                ("rustc-link-search", dir.to_string())
+            } else if let Some(lib) = f.strip_prefix("-l").filter(|l| l.starts_with(':') == false) {
+                ("rustc-link-lib", lib.to_string())
+            } else if archive.is_absolute() && f.ends_with(".a") {
+                let dir = archive.parent().unwrap().to_string_lossy().to_string();
+                directives.push(("rustc-link-search", dir));
+                ("rustc-link-lib", static_lib_of(&archive.file_name().unwrap().to_string_lossy()))
+            } else {
+                ("rustc-link-arg", f)
+            };
+            directives.push(directive);
+        }
+        directives
+    }
+
+    /// translate the flags of `recipe.c.combine.pattern` into (`rustc-link-search` |
+    /// `rustc-link-lib` | `rustc-link-arg`, value), in their order. the objects and the `.elf`
+    /// output are rustc's business and left out, so is the core archive, except inside a group,
+    /// where it is the `{archive_file_path}` of `values`. placeholders are expanded by
+    /// `values` and the board properties, a flag that is a single placeholder is split again.
+    /// `-T x`/`-u x`/`-L x` are joined into one argument, `-L` is a link search path and `-l` a
+    /// link lib, except between `-Wl,--start-group` and `-Wl,--end-group`, where libraries stay
+    /// link args to keep the group. only what `policy` forwards is returned, and the flags with
+    /// unknown placeholders come second.
+    pub fn link_directives(
+        info: &arduino_cli_util::Info,
+        flags: &[String],
+        values: &[(String, String)],
+        policy: &arduino_cli_util::LinkArgPolicy,
+    ) -> (Vec<(&'static str, String)>, Vec<String>) {
+        let lookup = |key: &str| {
+            values
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.to_owned())
+                .or_else(|| info.get_var(key))
+        };
+        let mut args = vec![];
+        let mut unresolved = vec![];
+        for f in flags {
+            let f = f.trim();
+            if f.contains("{object_files}") {
+                continue;
+            }
+            // `{archive_file}` or `{archive_file_path}`, placed once the group is known
+            if f.contains("{archive_file") {
+                args.push("{archive_file_path}".to_string());
+                continue;
+            }
+            let expanded = arduino_cli_util::expand_placeholders(f, lookup);
+            let single_placeholder = f.starts_with('{') && f.ends_with('}') && f[1..].contains('{') == false;
+            match single_placeholder {
+                true => args.extend(arduino_cli_util::split_quoted_string(&expanded)),
+                false => args.push(expanded),
+            }
+        }
+
+        let mut directives = vec![];
+        let mut in_group = false;
+        let mut args = args.into_iter().filter(|a| a.len() > 0);
+        while let Some(a) = args.next() {
+            let a = match a.as_str() {
+                "-T" | "-u" | "-L" => format!("{}{}", a, args.next().unwrap_or_default()),
+                _ => a,
+            };
+            // the output of `-o`, that flag itself is dropped with the recipe
+            if a.starts_with('-') == false && a.ends_with(".elf") {
+                continue;
+            }
+            if a == "{archive_file_path}" {
+                if let Some(core) = lookup("archive_file_path").filter(|_| in_group) {
+                    directives.push(("rustc-link-arg", core));
+                }
+                continue;
+            }
+            if a.contains('{') {
+                unresolved.push(a);
+                continue;
+            }
+            match a.as_str() {
+                "-Wl,--start-group" | "-Wl,-(" => in_group = true,
+                "-Wl,--end-group" | "-Wl,-)" => in_group = false,
+                _ => {}
+            }
+            if policy.forwards(&a) == false {
+                continue;
+            }
+            let directive = match (a.strip_prefix("-L"), a.strip_prefix("-l")) {
+                (Some(dir), _) => ("rustc-link-search", dir.to_string()),
+                (_, Some(lib)) if in_group == false => ("rustc-link-lib", lib.to_string()),
+                _ => ("rustc-link-arg", a),
+            };
+            directives.push(directive);
+        }
+        (directives, unresolved)
+    }
+
+    /// the (key, command) of the `recipe.hooks.<stage>.<n>.pattern` hooks, see [`recipe_commands`]
+    pub fn hook_commands(
+        info: &arduino_cli_util::Info,
+        stage: &str,
+        values: &[(String, String)],
+    ) -> Vec<(String, std::process::Command)> {
+        recipe_commands(info, &format!("recipe.hooks.{}.", stage), values)
+    }
+
+    /// the (key, command) of the `<prefix><name>.pattern` properties, numeric names in numeric
+    /// order (2 before 10) and then the others in alphabetic order, see [`recipe_command`]
+    pub fn recipe_commands(
+        info: &arduino_cli_util::Info,
+        prefix: &str,
+        values: &[(String, String)],
+    ) -> Vec<(String, std::process::Command)> {
+        let mut names = info
+            .orig_properties
+            .keys()
+            .filter_map(|k| k.strip_prefix(prefix)?.strip_suffix(".pattern"))
+            .filter(|n| n.len() > 0 && n.contains('.') == false)
+            .map(|n| (n.parse::<u32>().map_err(|_| ()), n.to_string()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+            .iter()
+            .filter_map(|(_, n)| recipe_command(info, &format!("{}{}.pattern", prefix, n), values))
+            .collect()
+    }
+
+    /// the command of the recipe `key`, its `<key>.<runtime.os>` variant wins. the placeholders
+    /// are filled from `values`, then from the board properties, and the result is split like the
+    /// arduino builder does. return the key that was used too.
+    pub fn recipe_command(
+        info: &arduino_cli_util::Info,
+        key: &str,
+        values: &[(String, String)],
+    ) -> Option<(String, std::process::Command)> {
+        let lookup = |key: &str| {
+            values
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.to_owned())
+                .or_else(|| info.get_var(key))
+        };
+        let os_key = format!("{}.{}", key, lookup("runtime.os").unwrap_or_default());
+        let (key, pattern) = match (info.get_var(&os_key), info.get_var(key)) {
+            (Some(p), _) => (os_key, p),
+            (None, Some(p)) => (key.to_string(), p),
+            (None, None) => return None,
+        };
+        let args = arduino_cli_util::split_quoted_string(&arduino_cli_util::expand_placeholders(&pattern, lookup));
+        let (program, args) = args.split_first()?;
+        let mut cmd = std::process::Command::new(program);
+        cmd.args(args);
+        Some((key, cmd))
+    }
+
+    /// run the [`hook_commands`] of `stage` that `policy` allows one after the other, and stop at
+    /// the first that fails. their output goes to stderr. return the hooks that ran or were skipped,
+    /// and the error of the failed one.
+    pub fn run_platform_hooks(
+        info: &arduino_cli_util::Info,
+        stage: &str,
+        values: &[(String, String)],
+        policy: &arduino_cli_util::HookPolicy,
+    ) -> (Vec<HookReport>, Result<(), String>) {
+        let mut reports = vec![];
+        for (key, mut cmd) in hook_commands(info, stage, values) {
+            let program = cmd.get_program().to_string_lossy().to_string();
+            let mut command = vec![program.to_owned()];
+            command.extend(cmd.get_args().map(|a| a.to_string_lossy().to_string()));
+            let skipped = if policy.allows(&key, &program) == false {
+                Some(format!(
+                    "{} hook `{}` skipped, allow `{}` or `{}` in \"hooks: allow\" of the down-stream config to run it",
+                    stage,
+                    command.join(" "),
+                    key,
+                    arduino_cli_util::HookPolicy::program_name(&program)
+                ))
+            } else if policy.dry_run {
+                Some(format!("{} hook `{}` not run, dry run", stage, command.join(" ")))
+            } else {
+                None
+            };
+            if skipped.is_some() {
+                reports.push(HookReport {
+                    stage: stage.to_string(),
+                    command,
+                    status: None,
+                    skipped,
+                });
+                continue;
+            }
+            let output = cmd.output();
+            reports.push(HookReport {
+                stage: stage.to_string(),
+                command: command.to_owned(),
+                status: output.as_ref().ok().and_then(|o| o.status.code()),
+                skipped: None,
+            });
+            let error = match output {
+                Ok(o) if o.status.success() => {
+                    eprint!("{}{}", String::from_utf8_lossy(&o.stdout), String::from_utf8_lossy(&o.stderr));
+                    continue;
+                }
+                Ok(o) => format!(
+                    "{}\n{}{}",
+                    o.status,
+                    String::from_utf8_lossy(&o.stdout),
+                    String::from_utf8_lossy(&o.stderr)
+                ),
+                Err(e) => e.to_string(),
+            };
+            return (
+                reports,
+                Err(format!("{} hook `{}` failed: {}", stage, command.join(" "), error.trim_end())),
+            );
+        }
+        (reports, Ok(()))
+    }
+
+    /// one `file:line:col: severity: message` line of gcc
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Diagnostic {
+        pub file: String,
+        pub line: u32,
+        pub column: Option<u32>,
+        /// `error`, `fatal error`, `warning` or `note`
+        pub severity: String,
+        pub message: String,
+    }
+
+    impl Diagnostic {
+        pub fn is_error(&self) -> bool {
+            self.severity.ends_with("error")
+        }
+    }
+
+    impl std::fmt::Display for Diagnostic {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self.column {
+                Some(c) => write!(f, "{}:{}:{}: {}: {}", self.file, self.line, c, self.severity, self.message),
+                None => write!(f, "{}:{}: {}: {}", self.file, self.line, self.severity, self.message),
+            }
+        }
+    }
+
+    /// kind (asm, c or cpp), configured builder and its sources
+    type RecipeBuild = (&'static str, cc::Build, Vec<PathBuf>);
+
+    /// arduino_build_report.json in OUT_DIR, what [`CompileFactory::compile`] compiled and linked.
+    /// the paths are absolute, CI can diff the reports of two commits.
+    #[derive(Debug, Clone, Default, serde::Serialize)]
+    pub struct BuildReport {
+        pub fqbn: String,
+        pub platform_version: Option<String>,
+        pub platform_path: Option<PathBuf>,
+        /// program of each platform recipe
+        pub tools: std::collections::BTreeMap<String, String>,
+        pub libraries: Vec<LibraryReport>,
+        pub sources: Vec<SourceReport>,
+        pub archives: Vec<ArchiveReport>,
+        /// `cargo:rustc-link-search` and `cargo:rustc-link-lib` values, and the link args of
+        /// `cargo:link_args` (`DEP_ARDUINO_SDK_LINK_ARGS`), in order
+        pub link_search: Vec<String>,
+        pub link_libs: Vec<String>,
+        pub link_args: Vec<String>,
+        pub hooks: Vec<HookReport>,
+    }
+
+    #[derive(Debug, Clone, serde::Serialize)]
+    pub struct LibraryReport {
+        pub name: String,
+        /// `version` of library.properties
+        pub version: Option<String>,
+        pub location: arduino_cli_util::LibraryLocation,
+        pub path: PathBuf,
+        /// archive name, if its sources were compiled
+        pub archive: Option<String>,
+        pub precompiled: Option<PathBuf>,
+    }
+
+    #[derive(Debug, Clone, serde::Serialize)]
+    pub struct SourceReport {
+        pub file: PathBuf,
+        pub compiler: String,
+        pub flags: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, serde::Serialize)]
+    pub struct ArchiveReport {
+        pub name: String,
+        pub path: PathBuf,
+        pub sources: Vec<PathBuf>,
+    }
+
+    #[derive(Debug, Clone, serde::Serialize)]
+    pub struct HookReport {
+        /// e.g. `prebuild`, `linking.prelink`
+        pub stage: String,
+        pub command: Vec<String>,
+        /// exit code, None if the hook was skipped, could not be started or was killed by a signal
+        pub status: Option<i32>,
+        /// why the hook did not run, see [`arduino_cli_util::HookPolicy`]
+        pub skipped: Option<String>,
+    }
+
+    /// one entry of compile_commands.json, see https://clang.llvm.org/docs/JSONCompilationDatabase.html
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+    pub struct CompileCommand {
+        pub directory: String,
+        pub file: String,
+        pub arguments: Vec<String>,
+    }
+
+    impl CompileCommand {
+        /// the command `b` runs for `file`, as cc-rs builds it
+        pub fn new(b: &cc::Build, file: &Path) -> Self {
+            let tool = b.get_compiler();
+            let mut arguments = vec![tool.path().to_string_lossy().to_string()];
+            arguments.extend(tool.args().iter().map(|a| a.to_string_lossy().to_string()));
+            arguments.push("-c".to_string());
+            arguments.push(file.to_string_lossy().to_string());
+            Self {
+                // cc-rs runs the compiler in the build script's working directory
+                directory: std::env::current_dir()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                file: file.to_string_lossy().to_string(),
+                arguments,
+            }
+        }
+    }
+
+    /// the located diagnostics in gcc's stderr, "In file included from" and caret lines are skipped
+    pub fn parse_gcc_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+        let mut result = vec![];
+        for l in stderr.lines() {
+            for severity in ["fatal error", "error", "warning", "note"] {
+                let marker = format!(": {}: ", severity);
+                let Some(pos) = l.find(&marker) else {
+                    continue;
+                };
+                let (location, message) = (&l[..pos], &l[pos + marker.len()..]);
+                // file:line or file:line:col, the file may contain ':' (C:\...)
+                let mut parts = location.rsplitn(3, ':').collect::<Vec<_>>();
+                parts.reverse();
+                let parsed = match parts.as_slice() {
+                    [file, line, col] if line.parse::<u32>().is_ok() && col.parse::<u32>().is_ok() => {
+                        Some((file.to_string(), line.parse().unwrap(), col.parse().ok()))
+                    }
+                    [file, line, col] if col.parse::<u32>().is_ok() => {
+                        Some((format!("{}:{}", file, line), col.parse().unwrap(), None))
+                    }
+                    [file, line] if line.parse::<u32>().is_ok() => {
+                        Some((file.to_string(), line.parse().unwrap(), None))
+                    }
+                    _ => None,
+                };
+                if let Some((file, line, column)) = parsed {
+                    result.push(Diagnostic {
+                        file,
+                        line,
+                        column,
+                        severity: severity.to_string(),
+                        message: message.to_string(),
+                    });
+                }
+                break;
+            }
+        }
+        result
+    }
+
+    /// compile `files` with `b`. if that fails, each file is compiled alone again to find
+    /// the broken ones, and their `file:line:col: error: ...` diagnostics are returned, one per line.
+    fn try_compile(b: &cc::Build, files: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+        let mut all = b.clone();
+        files.iter().for_each(|f| {
+            all.file(f);
+        });
+        let err = match all.try_compile_intermediates() {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+
+        let tool = b.get_compiler();
+        let tmp = tempdir().unwrap();
+        let mut report = vec![];
+        for f in files {
+            let mut cmd = tool.to_command();
+            cmd.arg("-c").arg(f).arg("-o").arg(tmp.path().join("diagnose.o"));
+            let output = match cmd.output() {
+                Ok(o) => o,
+                Err(e) => {
+                    report.push(format!("{}: {}", f.to_string_lossy(), e));
+                    continue;
+                }
+            };
+            if output.status.success() {
+                continue;
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let diagnostics = parse_gcc_diagnostics(&stderr);
+            if diagnostics.iter().any(|d| d.is_error()) {
+                diagnostics.iter().filter(|d| d.is_error()).for_each(|d| {
+                    report.push(d.to_string());
+                });
+            } else {
+                report.push(format!("{}: {}", f.to_string_lossy(), stderr.trim()));
+            }
+        }
+        if report.len() == 0 {
+            report.push(err.to_string());
+        }
+        Err(report.join("\n"))
+    }
+
+    /// [`try_compile`] each `(builder, files)` job on its own thread, the results are in the order
+    /// of `jobs`. cc-rs takes a token of cargo's jobserver (or one of `NUM_JOBS` without it) for
+    /// every compiler process, so the threads never run more compilers than cargo allows.
+    fn compile_parallel(jobs: &[(cc::Build, Vec<PathBuf>)]) -> Vec<Result<Vec<PathBuf>, String>> {
+        std::thread::scope(|s| {
+            let handles = jobs
+                .iter()
+                .map(|(b, files)| s.spawn(move || try_compile(b, files)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err("compiler thread panicked".to_string())))
+                .collect()
+        })
+    }
+
+    /// map the `roots` to their fixed names in the debug info and in `__FILE__`, so the objects do
+    /// not depend on where the sources and OUT_DIR are. `-ffile-prefix-map` needs gcc 8, older
+    /// compilers (e.g. avr-gcc 7.3) get `-fdebug-prefix-map`.
+    fn add_prefix_maps(b: &mut cc::Build, maps: &[(PathBuf, &str)]) {
+        let option = if b.is_flag_supported("-ffile-prefix-map=/a=/b").unwrap_or(false) {
+            "-ffile-prefix-map"
+        } else {
+            "-fdebug-prefix-map"
+        };
+        for (root, name) in maps {
+            b.flag(format!("{}={}={}", option, root.to_string_lossy(), name));
+        }
+    }
+
+    /// link (or copy) the `(source, object)` pairs into `dir` as `<source file name>.o`, sorted by
+    /// source. cc-rs names objects after a hash of the source dir, which differs between machines,
+    /// and `ar` keeps the member names.
+    fn stable_members(objects: &[(PathBuf, PathBuf)], dir: &Path) -> Vec<PathBuf> {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+        let mut sorted = objects.to_vec();
+        sorted.sort();
+        let mut names = BTreeSet::new();
+        sorted
+            .iter()
+            .map(|(src, obj)| {
+                let base = src.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let mut name = format!("{}.o", base);
+                // `utility/twi.c` and `twi.c` of one library
+                let mut n = 1;
+                while names.insert(name.clone()) == false {
+                    name = format!("{}.{}.o", base, n);
+                    n += 1;
+                }
+                let member = dir.join(name);
+                if std::fs::hard_link(obj, &member).is_err() {
+                    std::fs::copy(obj, &member)
+                        .unwrap_or_else(|e| panic!("{} -> {}: {}", obj.display(), member.display(), e));
+                }
+                member
+            })
+            .collect()
+    }
+
+    /// archive of the platform core and variant
+    pub const CORE_NAME: &str = "arduino_core";
+
+    /// archive of the down-stream "sources"
+    const SKETCH_NAME: &str = "arduino_sketch";
+
+    /// metadata key of the link args, `DEP_ARDUINO_SDK_LINK_ARGS` for the down-stream build script
+    /// (`links = "arduino_sdk"` in Cargo.toml)
+    const LINK_ARGS_KEY: &str = "link_args";
+
+    /// `libarduino_<library>.a` names of the external libraries, in their order. anything but
+    /// [A-Za-z0-9_] becomes `_`, and a name that is already taken (`Foo-Bar` after `Foo_Bar`, or a
+    /// library named `core`) gets a `_2`, `_3`, ... suffix, so no archive overwrites another.
+    fn external_archive_names(libraries: &[arduino_cli_util::Library]) -> Vec<String> {
+        let mut taken = vec![CORE_NAME.to_string(), SKETCH_NAME.to_string()];
+        for library in libraries {
+            let base = library
+                .name
+                .trim()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>();
+            let base = format!("arduino_{}", base);
+            let name = (1..)
+                .map(|n| match n {
+                    1 => base.to_owned(),
+                    _ => format!("{}_{}", base, n),
+                })
+                .find(|n| taken.contains(n) == false)
+                .unwrap();
+            taken.push(name);
+        }
+        taken.split_off(2)
+    }
+
+    /// whether `dir` holds a libarduino_core.a whose sources and headers are all unchanged
+    pub fn core_ready(dir: &Path) -> bool {
+        dir.join(format!("lib{}.a", CORE_NAME)).exists()
+            && std::fs::read_to_string(dir.join(format!("lib{}.a.deps", CORE_NAME)))
+                .ok()
+                .and_then(|s| object_cache::check_deps_manifest(&s))
+                .is_some()
+    }
+
+    /// fill the cache `dir` once, even when several cargo processes (e.g. `cargo build` and
+    /// rust-analyzer) race for it. the work is serialized by an exclusive lock on `<dir>.lock`,
+    /// `fill` writes into a temp dir next to `dir`, which is renamed into place only when `fill`
+    /// reports success. so a half-written archive is never visible under `dir`, and a stale one
+    /// is swapped out by renames, not removed in place.
+    pub fn populate_cache_dir(
+        dir: &Path,
+        ready: impl Fn(&Path) -> bool,
+        fill: impl FnOnce(&Path) -> bool,
+    ) {
+        if ready(dir) {
+            return;
+        }
+        let parent = dir.parent().expect("cache dir has no parent");
+        std::fs::create_dir_all(parent).expect("fail to create cache dir");
+
+        let mut lock_name = dir.file_name().unwrap().to_os_string();
+        lock_name.push(".lock");
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(parent.join(lock_name))
+            .expect("fail to open cache lock file");
+        fs4::fs_std::FileExt::lock_exclusive(&lock).expect("fail to lock cache dir");
+
+        // another process may have filled it while we were waiting
+        if ready(dir) {
+            return;
+        }
+
+        let tmp = tempfile::Builder::new()
+            .prefix(".tmp-")
+            .tempdir_in(parent)
+            .expect("fail to create temp cache dir");
+        if fill(tmp.path()) {
+            // a stale entry, e.g. a header of the core changed. it is moved aside rather than
+            // deleted in place, so `dir` is missing only between the two renames and a build
+            // linking the old archive never sees a half-deleted dir
+            let stale = if dir.exists() {
+                let stale = tempfile::Builder::new()
+                    .prefix(".old-")
+                    .tempdir_in(parent)
+                    .expect("fail to create temp cache dir");
+                let aside = stale.path().join("entry");
+                std::fs::rename(dir, &aside).expect("fail to move stale cache dir aside");
+                Some(stale)
+            } else {
+                None
+            };
+            std::fs::rename(tmp.keep(), dir).expect("fail to move cache dir into place");
+            drop(stale);
+        }
+    }
+
+    fn files_in_folder(folder: &str, pattern: &str) -> Vec<PathBuf> {
+        let pat = format!("{}/{}", folder, pattern);
+        let mut results = vec![];
+
+        if let Ok(g) = glob::glob(&pat) {
+            for f in g.filter_map(Result::ok) {
+                if f.ends_with("main.cpp") == false {
+                    results.push(f);
+                }
+            }
+        }
+        results
+    }
+    /// output c/c++ system header dirs
+    #[cfg(feature = "native_bindgen")]
+    fn output_gcc_sysheader_dirs(gcc_cmd_str: &str, cpp: bool) -> Option<Vec<String>> {
+        let mut cmd = std::process::Command::new(gcc_cmd_str);
+
+        cmd.arg("-x")
+            .arg(if cpp { "c++" } else { "c" })
+            .arg("-v")
+            .arg("-")
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped());
+        if let Ok(output) = cmd.output() {
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let mut dirs = vec![];
+            let (mut begin, mut end) = (false, false);
+            for l in stderr.lines() {
+                if l.contains("search starts here:") {
+                    begin = true;
+                }
+                if l.contains("End of search list") {
+                    end = true;
+                }
+                if begin == true && end == false {
+                    dirs.push(l);
+                }
+            }
+            let dest = dirs
+                .iter()
+                .filter(|s| PathBuf::from(s.trim()).is_dir() == true)
+                .map(|s| s.trim().to_string())
+                .collect::<Vec<_>>();
+
+                print!("cargo:warning= got gcc inc-dirs: {:?}",&dest);
+
+            return Some(dest);
+        }
+        None
+    }
+
+    #[cfg(feature = "native_bindgen")]
+    fn cargo_fmt_file(file: impl AsRef<Path>) {
+        let file = file.as_ref();
         let mut current = std::process::Command::new("rustfmt");
 
         if current.arg(file).spawn().is_err() {
@@ -831,25 +1904,1108 @@ mod compile_bindgen {
     #[cfg(test)]
     mod tests {
         use super::*;
+
+        /// a host builder with its objects in `obj_dir`
+        fn host_builder(obj_dir: &Path) -> cc::Build {
+            let mut b = cc::Build::new();
+            b.target("x86_64-unknown-linux-gnu")
+                .host("x86_64-unknown-linux-gnu")
+                .opt_level(0)
+                .cargo_metadata(false)
+                .out_dir(obj_dir);
+            b
+        }
+
+        /// [`arduino_cli_util::tests::test_info`] with the host compilers as its recipes
+        fn host_info(root: &Path, yaml: &str) -> arduino_cli_util::Info {
+            let mut info = arduino_cli_util::tests::test_info(root, yaml);
+            arduino_cli_util::tests::use_host_recipes(&mut info);
+            info
+        }
+
+        /// a [`Standalone`] host build into `out_dir`
+        fn host_standalone(out_dir: &Path, opt_level: &str, debug: bool) -> Standalone {
+            Standalone {
+                out_dir: out_dir.to_owned(),
+                target: "x86_64-unknown-linux-gnu".to_string(),
+                host: "x86_64-unknown-linux-gnu".to_string(),
+                opt_level: opt_level.to_string(),
+                debug,
+            }
+        }
+
         #[test]
         fn it_works() {
             let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("abc");
 
             let x = CompileFactory::new(arduino_cli_util::BUILD_PROPERTIES.as_ref().unwrap());
-            println!("{:#?}", x.compile_external_(Some(dir)));
+            println!("{:#?}", x.compile_external_(&Some(&dir), &Some(&dir), false));
         }
         #[test]
         fn it_works1() {
-            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("abc");
             let x = CompileFactory::new(arduino_cli_util::BUILD_PROPERTIES.as_ref().unwrap());
 
             println!("{:#?}", x);
         }
+        #[test]
+        fn parse_gcc_diagnostics_works() {
+            let stderr = "In file included from /lib/Servo/src/Servo.cpp:2:\n\
+                /lib/Servo/src/Servo.h:10:5: error: 'uint8' does not name a type\n\
+                \x20  10 |     uint8 x;\n\
+                C:\\Users\\me\\Servo.cpp:3: warning: unused variable 'y'\n\
+                cc1plus: fatal error: nofile.cpp: No such file or directory\n";
+            let d = parse_gcc_diagnostics(stderr);
+            assert_eq!(d.len(), 2);
+            assert_eq!(d[0].to_string(), "/lib/Servo/src/Servo.h:10:5: error: 'uint8' does not name a type");
+            assert!(d[0].is_error());
+            assert_eq!(d[1].file, "C:\\Users\\me\\Servo.cpp");
+            assert_eq!((d[1].line, d[1].column), (3, None));
+            assert!(!d[1].is_error());
+        }
+        #[test]
+        fn try_compile_reports_located_errors() {
+            let root = tempdir().unwrap();
+            let (good, bad) = (root.path().join("good.c"), root.path().join("bad.c"));
+            std::fs::write(&good, "int good(void) { return 1; }\n").unwrap();
+            std::fs::write(&bad, "int bad(void) {\n  return missing;\n}\n").unwrap();
+
+            let b = host_builder(&root.path().join("obj"));
+
+            assert_eq!(try_compile(&b, &[good.to_owned()]).unwrap().len(), 1);
+            let err = try_compile(&b, &[good, bad.to_owned()]).unwrap_err();
+            assert!(err.starts_with(&format!("{}:2:", bad.to_string_lossy())), "{}", err);
+            assert!(err.contains("'missing' undeclared"), "{}", err);
+        }
+        #[test]
+        fn compile_parallel_keeps_the_job_order() {
+            let root = tempdir().unwrap();
+            let mut jobs = vec![];
+            for i in 0..6 {
+                let src = root.path().join(format!("f{}.c", i));
+                let body = if i == 3 { "int f3(void) { return nope; }\n".to_string() } else { format!("int f{}(void) {{ return {}; }}\n", i, i) };
+                std::fs::write(&src, body).unwrap();
+                jobs.push((host_builder(&root.path().join(format!("obj{}", i))), vec![src]));
+            }
+
+            let results = compile_parallel(&jobs);
+            assert_eq!(results.len(), 6);
+            for (i, r) in results.iter().enumerate() {
+                match r {
+                    Ok(objects) => {
+                        assert_ne!(i, 3);
+                        assert!(objects[0].starts_with(root.path().join(format!("obj{}", i))));
+                    }
+                    Err(e) => {
+                        assert_eq!(i, 3);
+                        assert!(e.contains("f3.c:1:"), "{}", e);
+                    }
+                }
+            }
+        }
+        #[test]
+        fn archives_do_not_depend_on_the_build_dir() {
+            // the prefix maps of `Info::prefix_maps`, as `compile_core` applies them
+            let build = |root: &Path| {
+                let core = arduino_cli_util::tests::test_platform(root).join("cores/arduino");
+                for n in ["wiring.c", "abi.c", "hooks.c"] {
+                    let f = n.replace(".c", "");
+                    let src = format!("const char *{}(void) {{ return __FILE__; }}\n", f);
+                    arduino_cli_util::tests::write_file(&core.join(n), &src);
+                }
+                let info = host_info(root, "fqbn: arduino:avr:uno\n");
+                // the debug info names the sources and the compile dir
+                let x = CompileFactory::standalone(&info, host_standalone(&root.join("out"), "0", true));
+                let (dir, _) = x.compile_core(&root.join("cache").join(x.core_digest()));
+                std::fs::read(dir.join("libarduino_core.a")).unwrap()
+            };
+            let (a, b) = (tempdir().unwrap(), tempdir().unwrap());
+            let archive = build(a.path());
+            assert!(archive.windows(8).any(|w| w == b"wiring.c"));
+            assert!(archive.windows(13).any(|w| w == b"/arduino/data"));
+            assert_eq!(archive, build(b.path()));
+        }
+        #[test]
+        fn build_report_lists_libraries_and_link_lines() {
+            use arduino_cli_util::tests::write_file;
+            let root = tempdir().unwrap();
+            let servo = root.path().join("user/libraries/Servo");
+            write_file(&servo.join("library.properties"), "name=Servo\nversion=1.2.1\n");
+            write_file(&servo.join("src/Servo.cpp"), "#include \"Servo.h\"\n");
+            write_file(&servo.join("src/Servo.h"), "");
+            let mut info = arduino_cli_util::tests::test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nexternal_libraries:\n    - Servo\nhooks:\n    allow:\n        - recipe.hooks.prebuild.1.pattern\n",
+            );
+            info.orig_properties
+                .insert("recipe.hooks.prebuild.1.pattern".to_string(), "true {build.fqbn}".to_string());
+            info.orig_properties
+                .insert("recipe.hooks.prebuild.2.pattern".to_string(), "false".to_string());
+            let x = CompileFactory::new(&info);
+            x.cargo_link("rustc-link-search", "/out");
+            x.cargo_link("rustc-link-lib", "static=arduino_Servo");
+            x.cargo_link("rustc-link-arg", "-Wl,--gc-sections");
+            x.run_hooks("prebuild");
+            let archive = ArchiveReport {
+                name: "arduino_Servo".to_string(),
+                path: PathBuf::from("/out/libarduino_Servo.a"),
+                sources: vec![servo.join("src/Servo.cpp")],
+            };
+            x.write_report(vec![archive], root.path());
+
+            let json = std::fs::read_to_string(root.path().join("arduino_build_report.json")).unwrap();
+            let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(v["fqbn"], "arduino:avr:uno");
+            let library = &v["libraries"][0];
+            assert_eq!(library["name"], "Servo");
+            assert_eq!(library["version"], "1.2.1");
+            assert_eq!(library["location"], "user");
+            assert_eq!(library["archive"], "arduino_Servo");
+            assert_eq!(v["archives"][0]["sources"][0], servo.join("src/Servo.cpp").to_str().unwrap());
+            assert_eq!(v["link_search"], serde_json::json!(["/out"]));
+            assert_eq!(v["link_libs"], serde_json::json!(["static=arduino_Servo"]));
+            assert_eq!(v["link_args"], serde_json::json!(["-Wl,--gc-sections"]));
+            assert_eq!(v["hooks"][0]["command"], serde_json::json!(["true", "arduino:avr:uno"]));
+            assert_eq!(v["hooks"][0]["status"], 0);
+            assert_eq!(v["hooks"][1]["command"], serde_json::json!(["false"]));
+            assert!(v["hooks"][1]["skipped"].as_str().unwrap().contains("skipped"));
+        }
+        #[test]
+        fn hooks_run_in_numeric_order_and_stop_at_a_failure() {
+            let root = tempdir().unwrap();
+            let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\nproject_name: blink\n");
+            let policy = arduino_cli_util::HookPolicy {
+                allow: vec!["sh".to_string()],
+                dry_run: false,
+            };
+            let log = root.path().join("hooks.log");
+            for (n, word) in [("1", "one"), ("2", "two"), ("10", "ten")] {
+                info.orig_properties.insert(
+                    format!("recipe.hooks.prebuild.{}.pattern", n),
+                    format!("sh -c \"echo {} {{build.project_name}} >> '{}'\"", word, log.display()),
+                );
+            }
+            info.orig_properties.insert(
+                "recipe.hooks.prebuild.2.pattern.linux".to_string(),
+                format!("sh -c \"echo two-linux {{build.path}} >> '{}'\"", log.display()),
+            );
+            let values = hook_values(&info, Path::new("/out"), &info.project_name());
+            let (reports, result) = run_platform_hooks(&info, "prebuild", &values, &policy);
+            assert!(result.is_ok());
+            assert_eq!(reports.len(), 3);
+            let lines = std::fs::read_to_string(&log).unwrap();
+            if cfg!(target_os = "linux") {
+                assert_eq!(lines, "one blink\ntwo-linux /out\nten blink\n");
+            }
+
+            info.orig_properties
+                .insert("recipe.hooks.postbuild.1.pattern".to_string(), "sh -c \"echo broken >&2; exit 3\"".to_string());
+            info.orig_properties
+                .insert("recipe.hooks.postbuild.2.pattern".to_string(), "true".to_string());
+            let (reports, result) = run_platform_hooks(&info, "postbuild", &values, &policy);
+            assert_eq!(reports.len(), 1);
+            assert_eq!(reports[0].status, Some(3));
+            let err = result.unwrap_err();
+            assert!(err.starts_with("postbuild hook `sh -c echo broken >&2; exit 3` failed"), "{}", err);
+            assert!(err.contains("broken"), "{}", err);
+        }
+        #[test]
+        fn hooks_only_run_when_allowed() {
+            let root = tempdir().unwrap();
+            let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\n");
+            let marker = root.path().join("ran");
+            info.orig_properties.insert(
+                "recipe.hooks.prebuild.1.pattern".to_string(),
+                format!("touch '{}'", marker.display()),
+            );
+            let values = hook_values(&info, root.path(), "blink");
+            let run = |policy: &arduino_cli_util::HookPolicy| {
+                let (reports, result) = run_platform_hooks(&info, "prebuild", &values, policy);
+                assert!(result.is_ok());
+                reports.into_iter().next().unwrap()
+            };
+            let allow = |allow: &str, dry_run: bool| arduino_cli_util::HookPolicy {
+                allow: vec![allow.to_string()],
+                dry_run,
+            };
+
+            // nothing is allowed by default
+            let skipped = run(&info.hook_policy()).skipped.unwrap();
+            assert!(skipped.contains("allow `recipe.hooks.prebuild.1.pattern` or `touch`"), "{}", skipped);
+            assert!(run(&allow("bossac", false)).skipped.is_some());
+            assert!(run(&allow("touch", true)).skipped.unwrap().contains("dry run"));
+            assert!(marker.exists() == false);
+
+            assert!(run(&allow("recipe.hooks.prebuild.1.pattern", false)).skipped.is_none());
+            assert!(marker.exists());
+            assert!(run(&allow("*", false)).skipped.is_none());
+            assert!(allow("recipe.hooks.prebuild.1.pattern", false)
+                .allows("recipe.hooks.prebuild.1.pattern.windows", "C:\\tools\\bossac.exe"));
+            assert!(allow("bossac", false).allows("recipe.hooks.postbuild.1.pattern", "C:\\tools\\bossac.exe"));
+        }
+        #[test]
+        fn archive_names_never_collide() {
+            let root = tempdir().unwrap();
+            let libraries = ["Foo_Bar", "Foo-Bar", "Foo Bar", "core", "Servo"]
+                .map(|n| arduino_cli_util::Library::new(root.path().join(n), arduino_cli_util::LibraryLocation::User));
+            assert_eq!(
+                external_archive_names(&libraries),
+                ["arduino_Foo_Bar", "arduino_Foo_Bar_2", "arduino_Foo_Bar_3", "arduino_core_2", "arduino_Servo"]
+            );
+        }
+        #[test]
+        fn ldflags_reach_the_final_link() {
+            let flags = arduino_cli_util::split_quoted_string(
+                "-L/opt/sdk/lib -l algobsec -lm -l:libc_nano.a /opt/sdk/lib/bsec.a -Wl,--wrap=malloc",
+            );
+            let expected = [
+                ("rustc-link-search", "/opt/sdk/lib"),
+                ("rustc-link-lib", "algobsec"),
+                ("rustc-link-lib", "m"),
+                ("rustc-link-arg", "-l:libc_nano.a"),
+                ("rustc-link-search", "/opt/sdk/lib"),
+                ("rustc-link-lib", "static:+verbatim=bsec.a"),
+                ("rustc-link-arg", "-Wl,--wrap=malloc"),
+            ];
+            assert_eq!(ldflag_directives(&flags), expected.map(|(k, v)| (k, v.to_string())));
+            assert_eq!(static_lib_of("libPDM.a"), "static=PDM");
+        }
+        #[test]
+        fn link_directives_follow_the_combine_pattern() {
+            let root = tempdir().unwrap();
+            let yaml = "fqbn: esp32:esp32:esp32\nlink_args:\n  skip: [\"-lm\", \"-lgcc\", \"-Wl,--Map=*\"]\n";
+            let mut info = arduino_cli_util::tests::test_info(root.path(), yaml);
+            for (k, v) in [
+                ("compiler.sdk.path", "/sdk"),
+                ("compiler.c.elf.flags", "-T esp32.rom.ld -Tmemory.ld -u esp_app_desc -Wl,--wrap=esp_log_write -Wl,--gc-sections"),
+                ("compiler.c.elf.libs", "-lbt -lgcc -lm"),
+            ] {
+                info.orig_properties.insert(k.to_string(), v.to_string());
+            }
+            let pattern = "\"-Wl,--Map={build.path}/{build.project_name}.map\" \"-L{compiler.sdk.path}/lib\" -L {build.path} {compiler.c.elf.flags} -mlongcalls -Wl,--start-group {object_files} \"{archive_file_path}\" {compiler.c.elf.libs} -Wl,--end-group -lstdc++ -lm -Wl,-EL \"{build.path}/{build.project_name}.elf\" {build.unknown}";
+            let flags = arduino_cli_util::split_quoted_string(pattern);
+            let mut values = hook_values(&info, Path::new("/out"), "blink");
+            values.push(("archive_file_path".to_string(), "/cache/libarduino_core.a".to_string()));
+            let (directives, unresolved) = link_directives(&info, &flags, &values, &info.link_arg_policy());
+            let expected = [
+                ("rustc-link-search", "/sdk/lib"),
+                ("rustc-link-search", "/out"),
+                ("rustc-link-arg", "-Tesp32.rom.ld"),
+                ("rustc-link-arg", "-Tmemory.ld"),
+                ("rustc-link-arg", "-uesp_app_desc"),
+                ("rustc-link-arg", "-Wl,--wrap=esp_log_write"),
+                ("rustc-link-arg", "-Wl,--gc-sections"),
+                ("rustc-link-arg", "-mlongcalls"),
+                ("rustc-link-arg", "-Wl,--start-group"),
+                ("rustc-link-arg", "/cache/libarduino_core.a"),
+                ("rustc-link-arg", "-lbt"),
+                ("rustc-link-arg", "-Wl,--end-group"),
+                ("rustc-link-lib", "stdc++"),
+                ("rustc-link-arg", "-Wl,-EL"),
+            ];
+            assert_eq!(directives, expected.map(|(k, v)| (k, v.to_string())));
+            assert_eq!(unresolved, ["{build.unknown}"]);
+
+            // everything, the `-Wl,--Map` of the platform too
+            let all = arduino_cli_util::LinkArgPolicy { forward: vec!["*".to_string()], skip: vec![] };
+            let (directives, _) = link_directives(&info, &flags, &values, &all);
+            assert_eq!(directives[0], ("rustc-link-arg", "-Wl,--Map=/out/blink.map".to_string()));
+            assert!(directives.contains(&("rustc-link-arg", "-lgcc".to_string())));
+            assert!(directives.contains(&("rustc-link-lib", "m".to_string())));
+        }
+        #[test]
+        fn core_archive_is_linked_inside_the_group() {
+            let root = tempdir().unwrap();
+            let info = arduino_cli_util::tests::test_info(root.path(), "fqbn: esp32:esp32:esp32\n");
+            let mut values = hook_values(&info, Path::new("/out"), "blink");
+            values.push(("archive_file_path".to_string(), "/cache/libarduino_core.a".to_string()));
+            let policy = info.link_arg_policy();
+            let directives = |pattern: &str| {
+                let flags = arduino_cli_util::split_quoted_string(pattern);
+                link_directives(&info, &flags, &values, &policy).0.into_iter().map(|(_, v)| v).collect::<Vec<_>>()
+            };
+
+            let grouped = directives("-Wl,--start-group {object_files} \"{archive_file_path}\" -lbt -Wl,--end-group");
+            assert_eq!(grouped, ["-Wl,--start-group", "/cache/libarduino_core.a", "-lbt", "-Wl,--end-group"]);
+            // outside of a group cargo links the core as `static=arduino_core`
+            let avr = directives("-Wl,--gc-sections {object_files} \"{build.path}/{archive_file}\" \"-L{build.path}\" -lm");
+            assert_eq!(avr, ["-Wl,--gc-sections", "/out"]);
+        }
+        #[test]
+        fn target_mcu_must_match_the_board() {
+            let root = tempdir().unwrap();
+            let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:mega\n");
+            info.orig_properties.insert("build.mcu".to_string(), "atmega2560".to_string());
+            let flags = |f: &[&str]| f.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+
+            let spec = |cpu: &str| format!("{{\"arch\": \"avr\", \"cpu\": \"{}\"}}", cpu);
+            assert_eq!(target_mcu(&[], Some(&spec("atmega328p"))).as_deref(), Some("atmega328p"));
+            assert_eq!(target_mcu(&flags(&["-C", "target-cpu=atmega2560"]), None).as_deref(), Some("atmega2560"));
+            assert_eq!(target_mcu(&flags(&["-Ctarget-cpu=atmega168"]), Some(&spec("atmega328p"))).as_deref(), Some("atmega168"));
+            assert_eq!(target_mcu(&[], None), None);
+
+            assert_eq!(check_target_mcu(&info, "avr-atmega2560", &[], Some(&spec("atmega2560"))), Ok(None));
+            assert_eq!(check_target_mcu(&info, "avr-none", &[], None), Ok(None));
+            // a host build, e.g. of tests
+            assert_eq!(check_target_mcu(&info, "x86_64-unknown-linux-gnu", &[], None), Ok(None));
+            let err = check_target_mcu(&info, "avr-atmega328p", &[], Some(&spec("atmega328p"))).unwrap_err();
+            assert!(err.starts_with("the rust target `avr-atmega328p` compiles for atmega328p, but arduino:avr:mega has build.mcu atmega2560."), "{}", err);
+            assert!(check_target_mcu(&info, "avr-none", &flags(&["-Ctarget-cpu=atmega328p"]), None).is_err());
+        }
+        #[test]
+        fn builtin_target_name_only_warns() {
+            let root = tempdir().unwrap();
+            let mut info = arduino_cli_util::tests::test_info(root.path(), "fqbn: arduino:avr:uno\n");
+            info.orig_properties.insert("build.mcu".to_string(), "atmega328p".to_string());
+
+            // rustc's built-in target for the atmega328 also runs on an uno
+            let warning = check_target_mcu(&info, "avr-unknown-gnu-atmega328", &[], None).unwrap().unwrap();
+            assert!(warning.starts_with("the rust target `avr-unknown-gnu-atmega328` may compile for atmega328,"), "{}", warning);
+            let flags = ["-Ctarget-cpu=atmega328p".to_string()];
+            assert_eq!(check_target_mcu(&info, "avr-unknown-gnu-atmega328", &flags, None), Ok(None));
+
+            let specs = root.path().join("avr-specs");
+            arduino_cli_util::tests::write_file(&specs.join("avr-atmega328p.json"), "{\"cpu\": \"atmega328p\"}\n");
+            assert_eq!(find_target_spec("avr-atmega328p", root.path()), Some(specs.join("avr-atmega328p.json")));
+            assert_eq!(find_target_spec("avr-atmega2560", root.path()), None);
+        }
+        #[test]
+        fn standalone_compiles_core_and_libraries() {
+            use arduino_cli_util::tests::{test_platform, write_file};
+            let root = tempdir().unwrap();
+            let platform = test_platform(root.path());
+            write_file(&platform.join("cores/arduino/Arduino.h"), "int ticks(void);\n");
+            write_file(&platform.join("platform.txt"), "name=Test\n");
+            write_file(&platform.join("cores/arduino/wiring.c"), "#include \"Arduino.h\"\nint ticks(void) { return 2; }\n");
+            write_file(&platform.join("variants/standard/pins_arduino.h"), "");
+            let servo = root.path().join("user/libraries/Servo");
+            write_file(&servo.join("src/Servo.h"), "#include <Arduino.h>\n");
+            write_file(&servo.join("src/Servo.cpp"), "#include \"Servo.h\"\nint servo() { return ticks(); }\n");
+            write_file(&root.path().join("csrc/glue.c"), "int glue(void) { return 1; }\n");
+            let mut info = host_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nexternal_libraries:\n    - Servo\nsources: [csrc/*.c]\nhooks:\n    allow: [\"*\"]\n",
+            );
+            // each hook logs its stage and the archives that exist when it runs
+            for stage in ["sketch.prebuild", "sketch.postbuild", "libraries.prebuild", "libraries.postbuild"] {
+                info.orig_properties.insert(
+                    format!("recipe.hooks.{}.1.pattern", stage),
+                    format!("sh -c \"echo {} $(ls {{build.path}} | grep '[.]a$') >> {{build.path}}/hooks.log\"", stage),
+                );
+            }
+
+            let out = root.path().join("out");
+            let x = CompileFactory::standalone(&info, host_standalone(&out, "s", false));
+            x.compile(None);
+
+            assert!(out.join("core").join("libarduino_core.a").exists());
+            assert!(out.join("libarduino_Servo.a").exists());
+            // the headers of `-MMD` (of the core through its `.deps` manifest), the sources, the
+            // platform.txt and the config are the rerun-if-changed set
+            let rerun = x.rerun_if_changed.borrow();
+            for p in [
+                platform.join("cores/arduino/Arduino.h"),
+                platform.join("cores/arduino/wiring.c"),
+                servo.join("src/Servo.h"),
+                servo.join("src/Servo.cpp"),
+                platform.join("platform.txt"),
+                root.path().join("custom.yaml"),
+            ] {
+                assert!(rerun.contains(&p), "{} is not tracked", p.display());
+            }
+            drop(rerun);
+            // the sketch and the libraries are compiled between their own hooks
+            let log = std::fs::read_to_string(out.join("hooks.log")).unwrap();
+            assert_eq!(
+                log.lines().collect::<Vec<_>>(),
+                [
+                    "sketch.prebuild",
+                    "sketch.postbuild libarduino_sketch.a",
+                    "libraries.prebuild libarduino_sketch.a",
+                    "libraries.postbuild libarduino_Servo.a libarduino_sketch.a",
+                ]
+            );
+            let json = std::fs::read_to_string(out.join("compile_commands.json")).unwrap();
+            let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(v.as_array().unwrap().len(), 3);
+            assert!(out.join("arduino_build_report.json").exists());
+        }
+        #[test]
+        #[cfg(unix)]
+        fn uncached_core_is_linked_from_out_dir() {
+            use arduino_cli_util::tests::{test_platform, write_file};
+            use std::os::unix::fs::PermissionsExt;
+            let root = tempdir().unwrap();
+            let platform = test_platform(root.path());
+            let gone = platform.join("cores/arduino/gone.h");
+            write_file(&gone, "int ticks(void);\n");
+            write_file(&platform.join("cores/arduino/wiring.c"), "#include \"gone.h\"\nint ticks(void) { return 2; }\n");
+            // a compiler that removes a header of wiring.c, so its `.deps` manifest can not be written
+            let cc = root.path().join("cc.sh");
+            let script = format!("#!/bin/sh\ngcc \"$@\" || exit 1\ncase \"$*\" in *wiring.c*) rm -f {} ;; esac\n", gone.display());
+            write_file(&cc, &script);
+            std::fs::set_permissions(&cc, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let mut info = host_info(root.path(), "fqbn: arduino:avr:uno\n");
+            arduino_cli_util::tests::use_recipes(&mut info, &[("recipe.c.o.pattern", &format!("{} -Os", cc.display()))]);
+
+            let out = root.path().join("out");
+            let x = CompileFactory::standalone(&info, host_standalone(&out, "s", false));
+            x.compile(None);
+
+            assert!(out.join("core").exists() == false);
+            let search = x.report.borrow().link_search.to_owned();
+            assert_eq!(search, [out.join("uncached-core").to_string_lossy().to_string()]);
+            assert!(out.join("uncached-core/libarduino_core.a").exists());
+        }
+        #[test]
+        fn core_digest_follows_the_core_command_lines() {
+            let root = tempdir().unwrap();
+            let platform = arduino_cli_util::tests::test_platform(root.path());
+            arduino_cli_util::tests::write_file(&platform.join("cores/arduino/wiring.c"), "int ticks(void) { return 2; }\n");
+            let digest = |yaml: &str, out: &str, opt_level: &str| {
+                let info = host_info(root.path(), yaml);
+                let x = CompileFactory::standalone(&info, host_standalone(&root.path().join(out), opt_level, false));
+                let dir = x.core_archive_dir();
+                assert!(dir.starts_with(info.default_archive_dir()));
+                x.core_digest()
+            };
+            let uno = "fqbn: arduino:avr:uno\n";
+            // the out dir only reaches the prefix maps
+            assert_eq!(digest(uno, "a", "s"), digest(uno, "b", "s"));
+            assert_ne!(digest(uno, "a", "s"), digest(uno, "a", "2"));
+            let for_core = "fqbn: arduino:avr:uno\ncompile_flags:\n    for_core:\n        - -DFAST\n";
+            assert_ne!(digest(uno, "a", "s"), digest(for_core, "a", "s"));
+        }
+        #[test]
+        fn compile_commands_include_cached_objects() {
+            let root = tempdir().unwrap();
+            let info = arduino_cli_util::tests::test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\ncompile_commands: .\n",
+            );
+            let x = CompileFactory::new(&info);
+            let src = root.path().join("a.c");
+            std::fs::write(&src, "int a(void) { return 1; }\n").unwrap();
+            let cache = object_cache::ObjectCache::new(root.path().join("objects"));
+
+            let mut b = host_builder(&root.path().join("obj"));
+            b.define("FOO", "1");
+            x.compile_all_cached(vec![("c", b.clone(), vec![src.to_owned()])], &cache);
+
+            // a second factory only hits the cache, the command is recorded all the same
+            let y = CompileFactory::new(&info);
+            let objects = y.compile_all_cached(vec![("c", b.clone(), vec![src.to_owned()])], &cache);
+            assert_eq!(objects[0].as_ref().unwrap().len(), 1);
+            let out = root.path().join("out");
+            std::fs::create_dir_all(&out).unwrap();
+            y.write_compile_commands(&out);
+
+            let json = std::fs::read_to_string(out.join("compile_commands.json")).unwrap();
+            // copied next to custom.yaml
+            assert_eq!(
+                json,
+                std::fs::read_to_string(root.path().join("compile_commands.json")).unwrap()
+            );
+            let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let entries = v.as_array().unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0]["file"], src.to_string_lossy().as_ref());
+            let args = entries[0]["arguments"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|a| a.as_str().unwrap())
+                .collect::<Vec<_>>();
+            assert!(args.contains(&"-DFOO=1"), "{:?}", args);
+            assert!(args.contains(&"-MMD"), "{:?}", args);
+            assert_eq!(args[args.len() - 2..], ["-c", src.to_str().unwrap()]);
+        }
+        #[test]
+        fn populate_cache_dir_fills_once() {
+            let root = tempdir().unwrap();
+            let dir = root.path().join("arduino-lib").join("uno");
+            let fills = std::sync::atomic::AtomicUsize::new(0);
+
+            std::thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        populate_cache_dir(
+                            &dir,
+                            |d| d.join("libarduino_core.a").exists(),
+                            |tmp| {
+                                fills.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                                std::thread::sleep(std::time::Duration::from_millis(50));
+                                std::fs::write(tmp.join("libarduino_core.a"), b"!<arch>\n").is_ok()
+                            },
+                        );
+                    });
+                }
+            });
+            assert_eq!(fills.into_inner(), 1);
+            assert!(dir.join("libarduino_core.a").exists());
+            // only the lock file and the final dir are left behind
+            assert_eq!(std::fs::read_dir(dir.parent().unwrap()).unwrap().count(), 2);
+        }
+        #[test]
+        fn populate_cache_dir_failed_fill_is_not_visible() {
+            let root = tempdir().unwrap();
+            let dir = root.path().join("uno");
+
+            populate_cache_dir(&dir, |d| d.exists(), |tmp| {
+                let _ = std::fs::write(tmp.join("libarduino_core.a"), b"half");
+                false
+            });
+            assert!(!dir.exists());
+        }
+        #[test]
+        fn populate_cache_dir_swaps_a_stale_entry() {
+            let root = tempdir().unwrap();
+            let dir = root.path().join("uno");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("libarduino_core.a"), b"old").unwrap();
+            // a build still reading the old archive
+            let mut reader = std::fs::File::open(dir.join("libarduino_core.a")).unwrap();
+
+            let current = |d: &Path| std::fs::read(d.join("libarduino_core.a")).is_ok_and(|c| c == b"new");
+            populate_cache_dir(&dir, current, |tmp| std::fs::write(tmp.join("libarduino_core.a"), b"new").is_ok());
+            assert!(current(&dir));
+            let mut old = String::new();
+            std::io::Read::read_to_string(&mut reader, &mut old).unwrap();
+            assert_eq!(old, "old");
+            // the stale entry is gone, only the lock file and the final dir are left behind
+            assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 2);
+        }
+    }
+}
+
+/// cache of compiled objects, shared between builds (and crates, if the cache root is shared).
+/// an entry is keyed by the compiler, its full argument list, the source path and its contents.
+/// the headers the compiler reported through `-MMD` are recorded with their digests, and the
+/// entry is only reused while all of them are unchanged.
+pub(crate) mod object_cache {
+    use std::{
+        hash::Hasher,
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    /// FNV-1a. std's `DefaultHasher` is not guaranteed to be stable across rust releases,
+    /// but cache keys outlive a toolchain update.
+    pub struct Fnv64(u64);
+
+    impl Default for Fnv64 {
+        fn default() -> Self {
+            Fnv64(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl Hasher for Fnv64 {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 ^= *b as u64;
+                self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+    }
+
+    pub fn file_digest(path: &Path) -> Option<u64> {
+        let data = std::fs::read(path).ok()?;
+        let mut h = Fnv64::default();
+        h.write(&data);
+        Some(h.finish())
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ObjectCache {
+        dir: PathBuf,
+    }
+
+    impl ObjectCache {
+        pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+            Self {
+                dir: dir.as_ref().to_owned(),
+            }
+        }
+
+        /// key of `src` compiled by `cmd args...`, None if `src` is not readable
+        pub fn key(cmd: &Path, args: &[String], src: &Path) -> Option<String> {
+            let mut h = Fnv64::default();
+            h.write(cmd.to_string_lossy().as_bytes());
+            for a in args {
+                h.write(&[0]);
+                h.write(a.as_bytes());
+            }
+            h.write(&[0]);
+            h.write(src.to_string_lossy().as_bytes());
+            h.write_u64(file_digest(src)?);
+
+            let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+            Some(format!("{}-{:016x}", stem, h.finish()))
+        }
+
+        /// the cached object of `key`, if every header it depends on is unchanged
+        pub fn lookup(&self, key: &str) -> Option<PathBuf> {
+            let obj = self.dir.join(format!("{}.o", key));
+            let deps = std::fs::read_to_string(self.dir.join(format!("{}.deps", key))).ok()?;
+            if !obj.exists() {
+                return None;
+            }
+            check_deps_manifest(&deps)?;
+            // `arduino-sdk gc` drops the entries that were not used for a while
+            let _ = std::fs::File::options()
+                .append(true)
+                .open(&obj)
+                .and_then(|f| f.set_modified(std::time::SystemTime::now()));
+            Some(obj)
+        }
+
+        /// headers recorded for `key`
+        pub fn headers(&self, key: &str) -> Vec<PathBuf> {
+            std::fs::read_to_string(self.dir.join(format!("{}.deps", key)))
+                .map(|s| read_deps_manifest(&s))
+                .unwrap_or_default()
+        }
+
+        /// copy `obj` into the cache together with the headers listed in its `.d` file.
+        /// return the cached object path.
+        pub fn store(&self, key: &str, obj: &Path) -> Option<PathBuf> {
+            let headers = object_deps(obj);
+            let deps = deps_manifest(headers.iter().skip(1))?;
+
+            std::fs::create_dir_all(&self.dir).ok()?;
+            let dest = self.dir.join(format!("{}.o", key));
+            // the object is written before its deps, so `lookup` never sees a deps without object
+            write_atomic(&dest, &std::fs::read(obj).ok()?)?;
+            write_atomic(&self.dir.join(format!("{}.deps", key)), deps.as_bytes())?;
+            Some(dest)
+        }
+    }
+
+    pub fn write_atomic(dest: &Path, data: &[u8]) -> Option<()> {
+        let mut tmp = tempfile::NamedTempFile::new_in(dest.parent()?).ok()?;
+        tmp.write_all(data).ok()?;
+        tmp.persist(dest).ok()?;
+        Some(())
+    }
+
+    /// source and headers of `obj`, from the `.d` file the compiler wrote next to it
+    pub fn object_deps(obj: &Path) -> Vec<PathBuf> {
+        std::fs::read_to_string(obj.with_extension("d"))
+            .map(|s| parse_dep_file(&s))
+            .unwrap_or_default()
+    }
+
+    /// `<digest> <path>` lines of `files`, None if one of them is not readable
+    pub fn deps_manifest<'a>(files: impl Iterator<Item = &'a PathBuf>) -> Option<String> {
+        let mut deps = String::new();
+        for p in files {
+            deps.push_str(&format!("{:016x} {}\n", file_digest(p)?, p.to_string_lossy()));
+        }
+        Some(deps)
+    }
+
+    /// files of a [`deps_manifest`], None if one of them changed or vanished
+    pub fn check_deps_manifest(content: &str) -> Option<Vec<PathBuf>> {
+        let mut result = vec![];
+        for l in content.lines() {
+            let (digest, path) = l.split_once(' ')?;
+            let digest = u64::from_str_radix(digest, 16).ok()?;
+            if file_digest(Path::new(path)) != Some(digest) {
+                return None;
+            }
+            result.push(PathBuf::from(path));
+        }
+        Some(result)
+    }
+
+    pub fn read_deps_manifest(content: &str) -> Vec<PathBuf> {
+        content
+            .lines()
+            .filter_map(|l| l.split_once(' '))
+            .map(|(_digest, path)| PathBuf::from(path))
+            .collect()
+    }
+
+    /// files listed in a make rule written by `gcc -MMD`, the first one is the source itself
+    pub fn parse_dep_file(content: &str) -> Vec<PathBuf> {
+        let joined = content.replace("\\\r\n", " ").replace("\\\n", " ");
+        let mut result = Vec::<PathBuf>::new();
+
+        for line in joined.lines() {
+            // skip the phony `header.h:` targets of -MP
+            let deps = match line.split_once(": ") {
+                Some((_target, deps)) => deps,
+                None => continue,
+            };
+            let mut current = String::new();
+            let mut chars = deps.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if chars.peek() == Some(&' ') => {
+                        current.push(' ');
+                        chars.next();
+                    }
+                    ' ' | '\t' => {
+                        if current.len() > 0 {
+                            result.push(PathBuf::from(&current));
+                            current.clear();
+                        }
+                    }
+                    _ => current.push(c),
+                }
+            }
+            if current.len() > 0 {
+                result.push(PathBuf::from(&current));
+            }
+        }
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        #[test]
+        fn parse_dep_file_works() {
+            let d = "/tmp/obj/a.o: /lib/Servo/src/avr/Servo.cpp \\\n /lib/Servo/src/Servo.h \\\n /my\\ lib/x.h\n/lib/Servo/src/Servo.h:\n";
+            assert_eq!(
+                parse_dep_file(d),
+                vec![
+                    PathBuf::from("/lib/Servo/src/avr/Servo.cpp"),
+                    PathBuf::from("/lib/Servo/src/Servo.h"),
+                    PathBuf::from("/my lib/x.h"),
+                ]
+            );
+        }
+        #[test]
+        fn header_change_invalidates_entry() {
+            let dir = tempfile::tempdir().unwrap();
+            let (src, hdr, obj) = (
+                dir.path().join("a.c"),
+                dir.path().join("a.h"),
+                dir.path().join("out.o"),
+            );
+            std::fs::write(&src, "#include \"a.h\"").unwrap();
+            std::fs::write(&hdr, "int a;").unwrap();
+            std::fs::write(&obj, "obj").unwrap();
+            std::fs::write(
+                obj.with_extension("d"),
+                format!("{}: {} {}\n", obj.display(), src.display(), hdr.display()),
+            )
+            .unwrap();
+
+            let cache = ObjectCache::new(dir.path().join("cache"));
+            let key = ObjectCache::key(Path::new("avr-gcc"), &["-Os".to_string()], &src).unwrap();
+            assert!(cache.lookup(&key).is_none());
+            assert!(cache.store(&key, &obj).is_some());
+            assert!(cache.lookup(&key).is_some());
+
+            let other = ObjectCache::key(Path::new("avr-gcc"), &["-O2".to_string()], &src).unwrap();
+            assert_ne!(key, other);
+
+            std::fs::write(&hdr, "int b;").unwrap();
+            assert!(cache.lookup(&key).is_none());
+        }
+    }
+}
+
+/// a prebuilt libarduino_core.a as a tarball, so other machines and CI jobs skip compiling the core:
+/// `bundle.json`, the archive, a snapshot of the headers it was compiled against and optional
+/// bindings. the paths below the sketchbook and the arduino data dir are stored with the fixed
+/// names of [`arduino_cli_util::Info::prefix_maps`], an import maps them to the local roots and
+/// takes the archive only if every input has the digest it had on the exporting machine.
+pub(crate) mod core_bundle {
+    use super::{arduino_cli_util, compile_bindgen, object_cache};
+    use std::{
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    const MANIFEST: &str = "bundle.json";
+    /// sub dir of the bundle and of the core dir
+    const BINDINGS: &str = "bindings";
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    pub struct BundleManifest {
+        /// see [`arduino_cli_util::Info::core_cache_key`]
+        pub key: String,
+        /// [`compile_bindgen::CompileFactory::core_digest`] of the exporting build
+        pub core_digest: String,
+        pub archive: String,
+        /// every source and header of the archive, headers are in the `headers/` snapshot too
+        pub inputs: Vec<BundleInput>,
+        /// file names below `bindings/`
+        pub bindings: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    pub struct BundleInput {
+        /// the path with a fixed name for its root, see [`portable_path`]
+        pub path: String,
+        /// [`object_cache::file_digest`] as hex
+        pub digest: String,
+    }
+
+    /// pack the core of `core_dir`, compiled by the command lines of `core_digest`, and the `*.rs`
+    /// of `bindings_dir` (default: `<core_dir>/bindings`) into the gzipped tarball `bundle`
+    #[allow(dead_code)] // only the arduino-sdk tool exports
+    pub fn export(
+        info: &arduino_cli_util::Info,
+        core_digest: &str,
+        core_dir: &Path,
+        bindings_dir: Option<&Path>,
+        bundle: &Path,
+    ) -> Result<BundleManifest, String> {
+        let archive = format!("lib{}.a", compile_bindgen::CORE_NAME);
+        if compile_bindgen::core_ready(core_dir) == false {
+            return Err(format!(
+                "{}: no up to date {}, build or prebuild the core first",
+                core_dir.display(),
+                archive
+            ));
+        }
+        let deps = std::fs::read_to_string(core_dir.join(format!("{}.deps", archive)))
+            .map_err(|e| e.to_string())?;
+        let files = object_cache::check_deps_manifest(&deps).unwrap_or_default();
+
+        let staging = tempfile::tempdir().map_err(|e| e.to_string())?;
+        let copy = |from: &Path, to: &Path| {
+            std::fs::create_dir_all(to.parent().unwrap())
+                .and_then(|_| std::fs::copy(from, to))
+                .map_err(|e| format!("{}: {}", from.display(), e))
+        };
+        copy(&core_dir.join(&archive), &staging.path().join(&archive))?;
+
+        let roots = portable_roots(info);
+        let mut inputs = vec![];
+        for f in &files {
+            let path = portable_path(f, &roots);
+            if is_header(f) {
+                copy(f, &staging.path().join("headers").join(path.trim_start_matches('/')))?;
+            }
+            let digest = object_cache::file_digest(f).ok_or(format!("{}: not readable", f.display()))?;
+            inputs.push(BundleInput {
+                path,
+                digest: format!("{:016x}", digest),
+            });
+        }
+
+        let bindings_dir = bindings_dir.map(Path::to_path_buf).unwrap_or(core_dir.join(BINDINGS));
+        let mut bindings = rust_files(&bindings_dir);
+        for name in &bindings {
+            copy(&bindings_dir.join(name), &staging.path().join(BINDINGS).join(name))?;
+        }
+        bindings.sort();
+
+        let manifest = BundleManifest {
+            key: info.core_cache_key(core_digest),
+            core_digest: core_digest.to_string(),
+            archive,
+            inputs,
+            bindings,
+        };
+        let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+        std::fs::write(staging.path().join(MANIFEST), json).map_err(|e| e.to_string())?;
+
+        let bundle = arduino_cli_util::absolute(bundle).map_err(|e| e.to_string())?;
+        tar(Command::new("tar").arg("-czf").arg(&bundle).arg("-C").arg(staging.path()).arg("."))?;
+        Ok(manifest)
+    }
+
+    /// unpack `bundle` into `core_dir`, if it was exported for the fqbn and platform version of
+    /// `info`, compiled by the same command lines (`core_digest`), and the local platform has the
+    /// same sources and headers. an up to date core in `core_dir` is kept.
+    pub fn import(
+        info: &arduino_cli_util::Info,
+        core_digest: &str,
+        bundle: &Path,
+        core_dir: &Path,
+    ) -> Result<BundleManifest, String> {
+        let staging = tempfile::tempdir().map_err(|e| e.to_string())?;
+        tar(Command::new("tar").arg("-xzf").arg(bundle).arg("-C").arg(staging.path()))?;
+        let json = std::fs::read_to_string(staging.path().join(MANIFEST))
+            .map_err(|e| format!("{}: no {}, {}", bundle.display(), MANIFEST, e))?;
+        let manifest = serde_json::from_str::<BundleManifest>(&json)
+            .map_err(|e| format!("{}: {}", bundle.display(), e))?;
+
+        if manifest.core_digest != core_digest {
+            return Err(format!(
+                "{}: the core of the bundle was compiled by other command lines (core digest {}, here {}), \
+                 check the compile_flags, the compiler, the target, the opt-level and debug",
+                bundle.display(),
+                manifest.core_digest,
+                core_digest
+            ));
+        }
+        if manifest.key != info.core_cache_key(core_digest) {
+            return Err(format!(
+                "{}: the bundle is for {}, not for {}",
+                bundle.display(),
+                manifest.key,
+                info.core_cache_key(core_digest)
+            ));
+        }
+        let archive = format!("lib{}.a", compile_bindgen::CORE_NAME);
+        if manifest.archive != archive {
+            return Err(format!("{}: unexpected archive {}", bundle.display(), manifest.archive));
+        }
+
+        let roots = portable_roots(info);
+        let mut files = vec![];
+        for input in &manifest.inputs {
+            let local = local_path(&input.path, &roots);
+            let digest = object_cache::file_digest(&local).map(|d| format!("{:016x}", d));
+            if digest.as_deref() != Some(input.digest.as_str()) {
+                return Err(format!(
+                    "{}: differs from {} of the bundle, the local platform is not the exported one",
+                    local.display(),
+                    input.path
+                ));
+            }
+            files.push(local);
+        }
+        let deps = object_cache::deps_manifest(files.iter()).ok_or("core inputs are not readable")?;
+
+        let mut result = Ok(());
+        compile_bindgen::populate_cache_dir(core_dir, compile_bindgen::core_ready, |tmp| {
+            let copy = |name: &Path| {
+                std::fs::create_dir_all(tmp.join(name).parent().unwrap())
+                    .and_then(|_| std::fs::copy(staging.path().join(name), tmp.join(name)))
+                    .map_err(|e| format!("{}: {}", name.display(), e))
+            };
+            result = copy(Path::new(&archive))
+                .and_then(|_| {
+                    manifest.bindings.iter().try_for_each(|b| {
+                        // only plain file names, nothing outside of bindings/
+                        let name = Path::new(b).file_name().ok_or(format!("bad binding name {}", b))?;
+                        copy(&Path::new(BINDINGS).join(name)).map(|_| ())
+                    })
+                })
+                .and_then(|_| {
+                    std::fs::write(tmp.join(format!("{}.deps", archive)), &deps).map_err(|e| e.to_string())
+                });
+            result.is_ok()
+        });
+        result.map(|_| manifest)
+    }
+
+    /// the machine specific roots with their fixed names, the longest root first
+    fn portable_roots(info: &arduino_cli_util::Info) -> Vec<(PathBuf, &'static str)> {
+        let mut roots = info
+            .prefix_maps(Path::new(""))
+            .into_iter()
+            .filter(|(p, name)| p.is_absolute() && name.starts_with("/arduino/") && *name != "/arduino/out")
+            .collect::<Vec<_>>();
+        roots.reverse();
+        roots
+    }
+
+    /// `path` with its root replaced by the fixed name, e.g. `/arduino/data/packages/...`.
+    /// a path below no root is kept as it is.
+    pub fn portable_path(path: &Path, roots: &[(PathBuf, &'static str)]) -> String {
+        for (root, name) in roots {
+            if let Ok(rest) = path.strip_prefix(root) {
+                let rest = rest.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>();
+                return format!("{}/{}", name, rest.join("/"));
+            }
+        }
+        path.to_string_lossy().to_string()
+    }
+
+    /// the local path of a [`portable_path`]
+    pub fn local_path(path: &str, roots: &[(PathBuf, &'static str)]) -> PathBuf {
+        for (root, name) in roots {
+            if let Some(rest) = path.strip_prefix(name).and_then(|r| r.strip_prefix('/')) {
+                return rest.split('/').fold(root.to_owned(), |p, c| p.join(c));
+            }
+        }
+        PathBuf::from(path)
+    }
+
+    fn is_header(path: &Path) -> bool {
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+        ["c", "cpp", "cc", "cxx", "S", "s"].contains(&ext.as_ref()) == false
+    }
+
+    /// names of the `*.rs` files in `dir`
+    fn rust_files(dir: &Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .map(|r| {
+                r.flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "rs"))
+                    .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn tar(cmd: &mut Command) -> Result<(), String> {
+        let output = cmd.output().map_err(|e| format!("tar: {}", e))?;
+        if output.status.success() == false {
+            return Err(format!("tar: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use arduino_cli_util::tests::{test_info, test_platform, write_file};
+
+        #[test]
+        fn bundle_round_trip() {
+            let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+            let export_core = |root: &Path| {
+                let platform = test_platform(root);
+                write_file(&platform.join("cores/arduino/Arduino.h"), "int ticks(void);\n");
+                write_file(&platform.join("cores/arduino/wiring.c"), "int ticks(void) { return 2; }\n");
+                test_info(root, "fqbn: arduino:avr:uno\n")
+            };
+            // the same platform installed in two places
+            let (info_a, info_b) = (export_core(a.path()), export_core(b.path()));
+            let platform = test_platform(a.path());
+            let core_a = a.path().join("core");
+            write_file(&core_a.join("libarduino_core.a"), "!<arch>\n");
+            let inputs = [platform.join("cores/arduino/wiring.c"), platform.join("cores/arduino/Arduino.h")];
+            let deps = object_cache::deps_manifest(inputs.iter()).unwrap();
+            write_file(&core_a.join("libarduino_core.a.deps"), &deps);
+            write_file(&core_a.join("bindings/core.rs"), "pub fn f() {}\n");
+
+            let bundle = a.path().join("core.tar.gz");
+            let exported = export(&info_a, "d1", &core_a, None, &bundle).unwrap();
+            assert_eq!(exported.key, "arduino:avr:uno@1.0.0/d1");
+            assert_eq!(exported.bindings, ["core.rs"]);
+            assert!(exported.inputs[1].path.starts_with("/arduino/data/packages/"));
+
+            let core_b = b.path().join("core");
+            // a core compiled by other flags or another toolchain is refused
+            let err = import(&info_b, "d2", &bundle, &core_b).unwrap_err();
+            assert!(err.contains("other command lines (core digest d1, here d2)"), "{}", err);
+            assert!(core_b.exists() == false);
+            let imported = import(&info_b, "d1", &bundle, &core_b).unwrap();
+            assert_eq!(imported, exported);
+            assert!(compile_bindgen::core_ready(&core_b));
+            assert!(core_b.join("bindings/core.rs").exists());
+            let deps_b = std::fs::read_to_string(core_b.join("libarduino_core.a.deps")).unwrap();
+            assert!(deps_b.contains(&test_platform(b.path()).to_string_lossy().to_string()));
+
+            // another platform version, or modified headers, are refused
+            let mut info_c = info_b.clone();
+            info_c.orig_properties.insert("version".to_string(), "1.0.1".to_string());
+            let err = import(&info_c, "d1", &bundle, &b.path().join("core_c")).unwrap_err();
+            assert!(err.contains("not for arduino:avr:uno@1.0.1/d1"), "{}", err);
+            write_file(&test_platform(b.path()).join("cores/arduino/Arduino.h"), "int ticks(int);\n");
+            let err = import(&info_b, "d1", &bundle, &b.path().join("core_d")).unwrap_err();
+            assert!(err.contains("Arduino.h: differs"), "{}", err);
+            assert!(b.path().join("core_d").exists() == false);
+        }
     }
 }
 
-mod arduino_cli_util {
-    use serde_yaml;
+pub(crate) mod arduino_cli_util {
+    use lazy_static::lazy_static;
     use std::{
         collections::{HashMap, VecDeque},
         path::{Path, PathBuf},
@@ -858,14 +3014,17 @@ mod arduino_cli_util {
     type KVMap = HashMap<String, String>;
     pub const PRIVATE_CORE_DEDICATED: &str = "_private_core_dedicated";
     #[derive(Debug, Clone)]
-    struct DownStreamConfig {
+    pub struct DownStreamConfig {
         input: serde_yaml::Value,
+        /// the yaml file named by ARDUINO_SDK_CONFIG
+        path: Option<PathBuf>,
     }
     impl DownStreamConfig {
         pub fn new(env_arduino_sys: Option<&str>) -> Self {
             let default = DownStreamConfig {
                 input: serde_yaml::from_str::<serde_yaml::Value>(r#"{ "fqbn":"arduino:avr:uno" }"#)
                     .unwrap(),
+                path: env_arduino_sys.map(PathBuf::from),
             };
 
             if env_arduino_sys.is_none() {
@@ -882,7 +3041,10 @@ mod arduino_cli_util {
             };
 
             if let Ok(p) = serde_yaml::from_str::<serde_yaml::Value>(binding.as_str()) {
-                return DownStreamConfig { input: p };
+                return DownStreamConfig {
+                    input: p,
+                    path: default.path,
+                };
             }
 
             default
@@ -902,7 +3064,7 @@ mod arduino_cli_util {
 
         pub fn get_compile_flags<'a>(&'a self, key: &str) -> Option<VecDeque<String>> {
             if let Some(a) = self.input.get("compile_flags") {
-                let x = Self::get_strarray(&a, key)
+                let x = Self::get_strarray(a, key)
                     .map(|v| v.iter().map(|s| s.to_string()).collect::<VecDeque<_>>());
 
                 return x;
@@ -910,26 +3072,125 @@ mod arduino_cli_util {
 
             None
         }
-        pub fn get_external_libraries_path<'a>(&'a self, path_root: &Path) -> Option<Vec<PathBuf>> {
-            if let Some(x) = Self::get_strarray(&self.input, "external_libraries") {
-                if x.len() > 0 {
-                    let y = x
-                        .iter()
-                        .map(|s| {
-                            let dir = path_root.join(s.trim());
-                            let src_dir = dir.join("src");
-                            if src_dir.is_dir() {
-                                src_dir
-                            } else {
-                                dir
-                            }
-                        })
-                        .filter(|t| t.is_dir())
-                        .collect::<Vec<_>>();
-                    return Some(y);
+        /// names listed in "external_libraries"
+        pub fn get_external_libraries<'a>(&'a self) -> Vec<&'a str> {
+            Self::get_strarray(&self.input, "external_libraries")
+                .unwrap_or_default()
+                .iter()
+                .map(|s| s.trim())
+                .collect()
+        }
+
+        /// the "sources" globs, relative to the down-stream crate, see [`Self::crate_dir`].
+        /// none without a config file
+        pub fn get_sources_patterns(&self) -> Vec<PathBuf> {
+            let Some(base) = self.crate_dir() else {
+                return vec![];
+            };
+            Self::get_strarray(&self.input, "sources")
+                .unwrap_or_default()
+                .iter()
+                .map(|s| base.join(s.trim()))
+                .collect()
+        }
+
+        /// "compile_commands", where compile_commands.json is copied to, relative to the config file
+        pub fn get_compile_commands_path(&self) -> Option<PathBuf> {
+            self.get_path("compile_commands")
+        }
+
+        /// a core bundle to import instead of compiling the core, see [`super::core_bundle`]
+        pub fn get_core_bundle_path(&self) -> Option<PathBuf> {
+            self.get_path("core_bundle")
+        }
+
+        /// "hooks", see [`HookPolicy`]
+        pub fn get_hook_policy(&self) -> HookPolicy {
+            let hooks = self.input.get("hooks");
+            HookPolicy {
+                allow: hooks
+                    .and_then(|h| Self::get_strarray(h, "allow"))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|s| s.trim().to_string())
+                    .collect(),
+                dry_run: hooks
+                    .and_then(|h| h.get("dry_run"))
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+            }
+        }
+
+        /// "link_args", see [`LinkArgPolicy`]. a missing list keeps its default
+        pub fn get_link_arg_policy(&self) -> LinkArgPolicy {
+            let mut policy = LinkArgPolicy::default();
+            if let Some(l) = self.input.get("link_args") {
+                if let Some(forward) = Self::get_strarray(l, "forward") {
+                    policy.forward = forward.iter().map(|s| s.trim().to_string()).collect();
+                }
+                if let Some(skip) = Self::get_strarray(l, "skip") {
+                    policy.skip = skip.iter().map(|s| s.trim().to_string()).collect();
                 }
             }
-            None
+            policy
+        }
+
+        /// "project_name", the firmware name
+        pub fn get_project_name(&self) -> Option<String> {
+            self.input
+                .get("project_name")
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+        }
+
+        /// a path value, relative to the directory of the config file
+        fn get_path(&self, key: &str) -> Option<PathBuf> {
+            self.input
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| self.base_dir().join(s.trim()))
+        }
+
+        fn base_dir(&self) -> PathBuf {
+            self.path
+                .as_ref()
+                .and_then(|p| p.parent())
+                .map(Path::to_path_buf)
+                .unwrap_or_default()
+        }
+
+        /// the root of the down-stream crate: the nearest directory of the config file or above
+        /// it with a Cargo.toml, otherwise the directory of the config file. the build script
+        /// runs in this crate, so the CARGO_MANIFEST_DIR of the down-stream crate is not known.
+        pub fn crate_dir(&self) -> Option<PathBuf> {
+            let dir = absolute(self.path.as_ref()?.parent()?).ok()?;
+            let manifest = dir.ancestors().find(|d| d.join("Cargo.toml").is_file()).map(Path::to_path_buf);
+            Some(manifest.unwrap_or(dir))
+        }
+
+        /// libraries listed in "allow_failures", a compile error in them is only a warning
+        pub fn get_allow_failures<'a>(&'a self) -> Vec<&'a str> {
+            Self::get_strarray(&self.input, "allow_failures").unwrap_or_default()
+        }
+
+        /// libraries listed in "link_order" come first, in that order, the others keep their order
+        pub fn sort_by_link_order(&self, libraries: &mut [Library]) {
+            let order = Self::get_strarray(&self.input, "link_order").unwrap_or_default();
+            let rank = |n: &str| {
+                order
+                    .iter()
+                    .position(|o| o.trim() == n)
+                    .unwrap_or(order.len())
+            };
+            // stable sort, so unlisted libraries keep their relative order
+            libraries.sort_by_key(|l| rank(&l.name));
+        }
+
+        pub fn get_bool(&self, key: &str) -> bool {
+            if let Some(b) = self.input.get(key) {
+                return b.as_bool().unwrap_or(false);
+            }
+            false
         }
 
         fn get_strarray<'a>(input: &'a serde_yaml::Value, key: &str) -> Option<Vec<&'a str>> {
@@ -940,12 +3201,75 @@ mod arduino_cli_util {
                     return Some(ret);
                 }
             }
-            None
+            None
+        }
+    }
+
+    lazy_static! {
+        pub static ref BUILD_PROPERTIES: Option<Info> = Info::new();
+    }
+
+    /// which platform hooks run, "hooks" of the down-stream config. hooks are programs of third
+    /// party board packages, so none runs unless it is allowed.
+    #[derive(Debug, Clone, Default)]
+    pub struct HookPolicy {
+        /// hook keys (`recipe.hooks.prebuild.1.pattern`) or program names (`bossac`), `*` allows all
+        pub allow: Vec<String>,
+        /// only report the allowed hooks, run none
+        pub dry_run: bool,
+    }
+
+    impl HookPolicy {
+        /// whether the hook `key` that runs `program` is allowed. an OS variant
+        /// (`....pattern.linux`) is allowed by its plain key too.
+        pub fn allows(&self, key: &str, program: &str) -> bool {
+            let plain_key = key.rsplit_once(".pattern.").map(|(k, _)| format!("{}.pattern", k));
+            let program = Self::program_name(program);
+            self.allow
+                .iter()
+                .any(|a| a == "*" || a == key || Some(a) == plain_key.as_ref() || *a == program)
+        }
+
+        /// file name of `program` without `.exe`
+        pub fn program_name(program: &str) -> String {
+            // a windows path of a platform.txt is split on both separators, also on other hosts
+            let name = program.rsplit(['/', '\\']).next().unwrap_or_default().to_string();
+            name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+        }
+    }
+
+    /// which flags of `recipe.c.combine.pattern` reach the linker, "link_args" of the down-stream
+    /// config. a flag is forwarded if it matches a pattern of `forward` and none of `skip`; a
+    /// pattern is a flag, or a prefix ending with `*` (`-Wl,*`). `-T x` is matched as `-Tx`.
+    #[derive(Debug, Clone)]
+    pub struct LinkArgPolicy {
+        pub forward: Vec<String>,
+        pub skip: Vec<String>,
+    }
+
+    impl Default for LinkArgPolicy {
+        /// the search paths, libraries, linker scripts, undefined symbols, `-Wl,` options and the
+        /// machine flags. `-lm` and `-lgcc` are left to the rust target.
+        fn default() -> Self {
+            let forward = [
+                "-L*", "-l*", "-T*", "-u*", "-Wl,*", "-mmcu=*", "-mcpu=*", "-mthumb", "-mlongcalls", "-specs=*", "--specs=*",
+                "-nostdlib", "-nostartfiles",
+            ];
+            Self {
+                forward: forward.iter().map(|s| s.to_string()).collect(),
+                skip: vec!["-lm".to_string(), "-lgcc".to_string()],
+            }
         }
     }
 
-    lazy_static! {
-        pub static ref BUILD_PROPERTIES: Option<Info> = Info::new();
+    impl LinkArgPolicy {
+        pub fn forwards(&self, arg: &str) -> bool {
+            let matches = |pattern: &String| match pattern.strip_suffix('*') {
+                Some(prefix) => arg.starts_with(prefix),
+                None => arg == pattern,
+            };
+            self.forward.iter().any(matches) && self.skip.iter().any(matches) == false
+        }
     }
 
     #[derive(Debug, Clone)]
@@ -954,12 +3278,14 @@ mod arduino_cli_util {
         downstream_config: DownStreamConfig,
         pats: HashMap<String, RecipePattern>,
         user: String,
+        /// libraries to compile, in link order. see [`Info::resolve_libraries`]
+        libraries: Vec<Library>,
+        /// what [`Info::resolve_libraries`] found wrong, see [`Info::warnings`]
+        warnings: Vec<String>,
     }
 
     impl Info {
         pub fn new() -> Option<Self> {
-            let mut build_: Option<Info> = None;
-
             let downstream_config = if let Ok(env_arduino_sys) = std::env::var("ARDUINO_SDK_CONFIG")
             {
                 DownStreamConfig::new(Some(env_arduino_sys.as_str()))
@@ -972,16 +3298,29 @@ mod arduino_cli_util {
                 let x = fqbn.splitn(4, ":").collect::<Vec<_>>();
                 let (packager, arch, _boardid) = (x[0], x[1], x[2]);
                 println!("cargo:rustc-cfg={}_{}",packager,arch);
+            }
+            Self::from_config(downstream_config)
+        }
 
+        /// resolve the board and the libraries of `downstream_config` by arduino-cli.
+        /// unlike [`Self::new`] it prints no cargo directive, for tools.
+        pub fn from_config(downstream_config: DownStreamConfig) -> Option<Self> {
+            let mut build_: Option<Info> = None;
+
+            if let Some(fqbn) = downstream_config.get_fqbn() {
                 if let Some(orig_properties) = get_build_properties(fqbn) {
                     let pats = get_patterns_(&orig_properties, &downstream_config);
                     if let Some(user) = get_user() {
-                        build_.replace(Info {
+                        let mut info = Info {
                             orig_properties,
                             downstream_config,
                             pats,
                             user,
-                        });
+                            libraries: vec![],
+                            warnings: vec![],
+                        };
+                        (info.libraries, info.warnings) = info.resolve_libraries();
+                        build_.replace(info);
                     }
                 }
             }
@@ -1000,11 +3339,12 @@ mod arduino_cli_util {
 
         /// var defined in board.txt and platform.txt
         pub fn get_var(&self, key: &str) -> Option<String> {
-            return self.orig_properties.get(key.into()).cloned();
+            return self.orig_properties.get(key).cloned();
         }
         pub fn get_pat(&self, key: &str) -> Option<RecipePattern> {
-            return self.pats.get(key.into()).cloned();
+            return self.pats.get(key).cloned();
         }
+        /// include dirs of the core and the variant
         pub fn core_incs(&self) -> Vec<String> {
             let mut result = Vec::<String>::new();
             if let Some(p) = self.get_var("build.core.path") {
@@ -1013,76 +3353,663 @@ mod arduino_cli_util {
             if let Some(p) = self.get_var("build.variant.path") {
                 result.push(p.to_owned());
             }
-            for p in self.get_arduino_libraries_path() {
-                result.push(p);
-            }
 
             result
         }
 
+        /// include dirs of all the libraries to compile
+        pub fn libraries_incs(&self) -> Vec<String> {
+            self.libraries
+                .iter()
+                .flat_map(|l| l.include_dirs())
+                .map(|p| p.to_string_lossy().to_string())
+                .collect()
+        }
+
+        /// source dirs of the libraries listed in "external_libraries"
+        #[cfg(feature = "native_bindgen")]
         pub fn get_external_libraries_path(&self) -> Vec<String> {
+            let names = self.downstream_config.get_external_libraries();
+            self.libraries
+                .iter()
+                .filter(|l| names.contains(&l.name.as_str()))
+                .map(|l| l.src_dir.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        }
+
+        /// the files this configuration is read from: the ARDUINO_SDK_CONFIG yaml and the
+        /// platform/board property files of the selected platform.
+        pub fn config_inputs(&self) -> Vec<PathBuf> {
             let mut result = Vec::<PathBuf>::new();
+            if let Some(p) = &self.downstream_config.path {
+                result.push(p.to_owned());
+            }
+            // the folder a "sources" glob starts from, so a new file in it is noticed
+            for p in self.downstream_config.get_sources_patterns() {
+                let dir = p
+                    .ancestors()
+                    .find(|a| !a.to_string_lossy().contains(['*', '?', '[']))
+                    .map(Path::to_path_buf);
+                if let Some(dir) = dir.filter(|d| d.is_dir()) {
+                    result.push(dir);
+                }
+            }
+            if let Some(p) = self.get_var("runtime.platform.path") {
+                for f in ["platform.txt", "platform.local.txt", "boards.txt", "boards.local.txt"] {
+                    let f = Path::new(&p).join(f);
+                    if f.exists() {
+                        result.push(f);
+                    }
+                }
+            }
+            result
+        }
 
-            if let Some(v) = self
+        /// the down-stream C/C++/asm shims matched by "sources", sorted
+        pub fn get_sketch_sources(&self) -> Vec<PathBuf> {
+            let mut result = self
                 .downstream_config
-                .get_external_libraries_path(&Path::new(&self.user).join("libraries"))
-            {
-                result.extend(v);
+                .get_sources_patterns()
+                .iter()
+                .flat_map(|p| Self::sketch_sources_of(p))
+                .collect::<Vec<_>>();
+            result.sort();
+            result.dedup();
+            result
+        }
+
+        /// the "sources" globs that match no C/C++/asm file, likely a typo or a wrong base dir
+        pub fn unmatched_sources_patterns(&self) -> Vec<PathBuf> {
+            self.downstream_config
+                .get_sources_patterns()
+                .into_iter()
+                .filter(|p| Self::sketch_sources_of(p).len() == 0)
+                .collect()
+        }
+
+        fn sketch_sources_of(pattern: &Path) -> Vec<PathBuf> {
+            glob::glob(&pattern.to_string_lossy())
+                .map(|g| g.filter_map(Result::ok).collect::<Vec<_>>())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|f| f.extension().is_some_and(|e| e == "c" || e == "cpp" || e == "S"))
+                .collect()
+        }
+
+        /// libraries to compile, in link order: the "external_libraries" and every sketchbook or
+        /// platform library they reach through `#include`
+        pub fn libraries(&self) -> &[Library] {
+            &self.libraries
+        }
+
+        /// all libraries of the sketchbook and of the platform, sketchbook first
+        fn installed_libraries(&self) -> Vec<Library> {
+            let mut result = vec![];
+            let mut roots = vec![(
+                Path::new(&self.user).join("libraries"),
+                LibraryLocation::User,
+            )];
+            if let Some(p) = self.get_var("runtime.platform.path") {
+                roots.push((Path::new(&p).join("libraries"), LibraryLocation::Platform));
             }
 
+            for (root, location) in roots {
+                let mut entrys = get_dir_entries(&root).unwrap_or_default();
+                entrys.sort_by_key(|e| e.file_name());
+                for entry in &entrys {
+                    if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                        result.push(Library::new(entry.path(), location));
+                    }
+                }
+            }
             result
-                .iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect::<Vec<_>>()
         }
 
-        pub fn get_arduino_libraries_path(&self) -> Vec<String> {
-            let mut final_ret = Vec::<String>::new();
-            if let Some(p) = self.get_var("runtime.platform.path") {
-                let root = Path::new(p.as_str()).join("libraries");
-                let mut result = vec![];
-                if let Ok(entrys) = get_dir_entries(&root) {
-                    for entry in &entrys {
-                        if let Ok(t) = &entry.file_type() {
-                            if t.is_dir() {
-                                let src_dir = entry.path().join("src");
-                                if src_dir.is_dir() {
-                                    result.push(src_dir);
-                                } else {
-                                    result.push(entry.path());
-                                }
-                            }
+        /// Arduino-builder style library detection. start from the "external_libraries" and the
+        /// "sources" shims, scan the `#include` directives of every file of a selected library
+        /// (and of the shims and the headers next to them), and select the library
+        /// that provides each header not found in the core, the variant or a selected library.
+        /// the `depends` of library.properties are selected as well.
+        /// return the libraries and the warnings about them.
+        fn resolve_libraries(&self) -> (Vec<Library>, Vec<String>) {
+            let arch = self.get_arch();
+            let installed = self.installed_libraries();
+            let mut selected = Vec::<Library>::new();
+            let mut queue = VecDeque::<PathBuf>::new();
+            let mut warnings = Vec::<String>::new();
+
+            // select `l` and everything it depends on
+            let select = |l: &Library,
+                          selected: &mut Vec<Library>,
+                          queue: &mut VecDeque<PathBuf>,
+                          warnings: &mut Vec<String>| {
+                let mut pending = vec![l.clone()];
+                while let Some(l) = pending.pop() {
+                    if selected.iter().any(|s| s.name == l.name) {
+                        continue;
+                    }
+                    if !l.supports_arch(&arch) {
+                        warnings.push(format!(
+                            "library '{}' claims to run on {} architecture(s) and may be incompatible with your current board which runs on {} architecture(s)",
+                            l.name,
+                            l.get_property("architectures").unwrap_or_default(),
+                            arch
+                        ));
+                    }
+                    for d in l.depends() {
+                        match find_library(&installed, &d, &arch) {
+                            Some(dep) => pending.push(dep.clone()),
+                            None => warnings.push(format!(
+                                "library '{}' depends on '{}', which is not installed",
+                                l.name, d
+                            )),
                         }
                     }
+                    queue.extend(l.scan_files(&arch));
+                    selected.push(l);
                 }
-                result
-                    .iter()
-                    .for_each(|i| final_ret.push(i.to_string_lossy().to_string()));
+            };
+
+            for name in self.downstream_config.get_external_libraries() {
+                match find_library(&installed, name, &arch) {
+                    Some(l) => select(l, &mut selected, &mut queue, &mut warnings),
+                    None => warnings.push(format!(
+                        "external library '{}' not found in the sketchbook or the platform libraries",
+                        name
+                    )),
+                }
+            }
+
+            let shims = self.get_sketch_sources();
+            let shim_dirs = shims
+                .iter()
+                .filter_map(|f| f.parent().map(Path::to_path_buf))
+                .collect::<std::collections::BTreeSet<_>>();
+            for dir in shim_dirs {
+                let pat = format!("{}/*.h", glob::Pattern::escape(&dir.to_string_lossy()));
+                if let Ok(g) = glob::glob(&pat) {
+                    queue.extend(g.filter_map(Result::ok));
+                }
+            }
+            queue.extend(shims);
+
+            let core_incs = self.core_incs();
+            while let Some(file) = queue.pop_front() {
+                let content = match std::fs::read(&file) {
+                    Ok(c) => String::from_utf8_lossy(&c).to_string(),
+                    Err(_) => continue,
+                };
+                for header in scan_includes(&content) {
+                    let local = file.parent().map(|p| p.join(&header).is_file());
+                    if local == Some(true)
+                        || core_incs.iter().any(|i| Path::new(i).join(&header).is_file())
+                        || selected.iter().any(|l| l.provides(&header))
+                    {
+                        continue;
+                    }
+                    // not found anywhere is fine, e.g. toolchain headers like <avr/io.h>
+                    if let Some(l) = best_library_for(&installed, &header, &arch) {
+                        select(l, &mut selected, &mut queue, &mut warnings);
+                    }
+                }
+            }
+
+            self.downstream_config.sort_by_link_order(&mut selected);
+            (selected, warnings)
+        }
+
+        /// the libraries that were not found or may not fit the board, printed by
+        /// [`super::compile_bindgen::CompileFactory::compile`]
+        pub fn warnings(&self) -> &[String] {
+            &self.warnings
+        }
+
+        /// the configured copy of compile_commands.json, a directory or a file
+        pub fn compile_commands_path(&self) -> Option<PathBuf> {
+            self.downstream_config.get_compile_commands_path()
+        }
+
+        /// the configured core bundle
+        pub fn core_bundle_path(&self) -> Option<PathBuf> {
+            self.downstream_config.get_core_bundle_path()
+        }
+
+        /// the value of `key` in the down-stream config, for the settings of the arduino-sdk tool
+        #[allow(dead_code)] // the build script has typed getters
+        pub fn config_value(&self, key: &str) -> Option<&serde_yaml::Value> {
+            self.downstream_config.input.get(key)
+        }
+
+        /// the configured [`HookPolicy`]
+        pub fn hook_policy(&self) -> HookPolicy {
+            self.downstream_config.get_hook_policy()
+        }
+
+        /// the configured [`LinkArgPolicy`]
+        pub fn link_arg_policy(&self) -> LinkArgPolicy {
+            self.downstream_config.get_link_arg_policy()
+        }
+
+        /// the dir of the down-stream config, the sketch dir of the hooks
+        pub fn source_dir(&self) -> PathBuf {
+            self.downstream_config.base_dir()
+        }
+
+        /// `{build.project_name}` of the hooks: "project_name" of the down-stream config,
+        /// otherwise the name of its dir, which is usually the down-stream crate
+        pub fn project_name(&self) -> String {
+            self.downstream_config
+                .get_project_name()
+                .or_else(|| Some(self.source_dir().file_name()?.to_string_lossy().to_string()))
+                .unwrap_or("sketch".to_string())
+        }
+
+        /// whether `library` is listed in "allow_failures"
+        pub fn allows_failures(&self, library: &Library) -> bool {
+            self.downstream_config
+                .get_allow_failures()
+                .iter()
+                .any(|n| library.matches_name(n))
+        }
+
+        /// `src/<build.mcu>/<build.fpu>-<build.float-abi>/` or `src/<build.mcu>/` of a library with
+        /// `precompiled=true|full`, the first one that holds a `.a`
+        pub fn precompiled_dir(&self, library: &Library) -> Option<PathBuf> {
+            let precompiled = library.get_property("precompiled")?;
+            if precompiled != "true" && precompiled != "full" {
+                return None;
+            }
+            let mcu_dir = library.src_dir.join(self.get_var("build.mcu")?);
+            let mut candidates = vec![];
+            if let (Some(fpu), Some(abi)) = (self.get_var("build.fpu"), self.get_var("build.float-abi")) {
+                candidates.push(mcu_dir.join(format!("{}-{}", fpu, abi)));
             }
+            candidates.push(mcu_dir);
 
-            final_ret
+            candidates.into_iter().find(|d| {
+                get_dir_entries(d)
+                    .map(|es| es.iter().any(|e| e.path().extension().is_some_and(|x| x == "a")))
+                    .unwrap_or(false)
+            })
+        }
+
+        /// whether the sources of `library` are compiled, a `precompiled=full` library with an
+        /// archive for this board is only linked
+        pub fn compile_sources_of(&self, library: &Library) -> bool {
+            library.get_property("precompiled").as_deref() != Some("full")
+                || self.precompiled_dir(library).is_none()
+        }
+
+        pub fn get_arch(&self) -> String {
+            let fqbn = self.get_fqbn();
+            fqbn.split(':').nth(1).unwrap_or_default().to_string()
         }
-        /// (relative to CARGO_MANIFEST_DIR path, absolute path)
+
+        /// directory of the prebuilt libarduino_core.a of this board, it holds one sub dir per
+        /// [`super::compile_bindgen::CompileFactory::core_digest`]. `ARDUINO_SDK_CACHE_DIR` wins, then the user cache dir if the down-stream config sets
+        /// `shared_core_cache: true`, otherwise it is private to the build in OUT_DIR.
+        /// nothing is ever written below CARGO_MANIFEST_DIR.
         pub fn default_archive_dir(&self) -> PathBuf {
+            self.cache_root().join(self.archive_relative_dir())
+        }
+
+        /// `<fqbn>@<platform version>/<core digest>`, a core bundle is only imported for the same
+        /// key, see [`super::core_bundle`] and [`super::compile_bindgen::CompileFactory::core_digest`]
+        pub fn core_cache_key(&self, core_digest: &str) -> String {
+            format!("{}@{}/{}", self.get_fqbn(), self.get_var("version").unwrap_or_default(), core_digest)
+        }
+
+        /// directory of the compiled external library objects, see [`super::object_cache`].
+        /// it follows the same cache root as [`Self::default_archive_dir`].
+        pub fn object_cache_dir(&self) -> PathBuf {
+            self.cache_root().join("arduino-lib").join("objects")
+        }
+
+        /// the arduino-cli data dir, the parent of the `packages` folder the platform is installed in
+        pub fn data_dir(&self) -> Option<PathBuf> {
+            let platform = PathBuf::from(self.get_var("runtime.platform.path")?);
+            platform
+                .ancestors()
+                .find(|a| a.file_name().is_some_and(|n| n == "packages"))
+                .and_then(|p| p.parent())
+                .map(Path::to_path_buf)
+        }
+
+        /// the roots that differ between machines, and the fixed names they get in the objects:
+        /// the sketchbook, the data dir, the config dir, `out_dir` and the working dir of the compiler.
+        /// more specific roots come last, gcc tries the last given map first.
+        pub fn prefix_maps(&self, out_dir: &Path) -> Vec<(PathBuf, &'static str)> {
+            let mut maps = vec![(PathBuf::from(&self.user), "/arduino/user")];
+            if let Some(d) = self.data_dir() {
+                maps.push((d, "/arduino/data"));
+            }
+            let config_dir = self.downstream_config.base_dir();
+            if config_dir.as_os_str().len() > 0 {
+                maps.push((config_dir, "/arduino/sketch"));
+            }
+            maps.push((out_dir.to_owned(), "/arduino/out"));
+            if let Ok(d) = std::env::current_dir() {
+                maps.push((d, "."));
+            }
+            maps.sort_by_key(|(p, _)| p.as_os_str().len());
+            maps
+        }
+
+        fn cache_root(&self) -> PathBuf {
+            select_cache_root(
+                std::env::var("ARDUINO_SDK_CACHE_DIR").ok(),
+                self.downstream_config.get_bool("shared_core_cache"),
+                user_cache_dir,
+                out_dir,
+            )
+        }
+
+        /// arduino-lib/<packager>/<arch>/<version>/cores/<core>/<boardid>/<variant>
+        fn archive_relative_dir(&self) -> PathBuf {
             let fqbn = self.downstream_config.get_fqbn().unwrap();
             let x = fqbn.splitn(4, ":").collect::<Vec<_>>();
             let (packager, arch, boardid) = (x[0], x[1], x[2]);
 
-            let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
-
-            let relative_p = Path::new("arduino-lib")
+            Path::new("arduino-lib")
                 .join(packager)
                 .join(arch)
                 .join(self.get_var("version").unwrap())
                 .join("cores")
                 .join(self.get_var("build.core").unwrap())
                 .join(boardid)
-                .join(self.get_var("build.variant").unwrap());
+                .join(self.get_var("build.variant").unwrap())
+        }
+    }
+
+    /// the cache root of [`Info::default_archive_dir`]: `cache_dir` (ARDUINO_SDK_CACHE_DIR) wins,
+    /// then the user cache dir if the cache is `shared`, otherwise OUT_DIR
+    fn select_cache_root(
+        cache_dir: Option<String>,
+        shared: bool,
+        user_cache_dir: impl FnOnce() -> Option<PathBuf>,
+        out_dir: impl Fn() -> PathBuf,
+    ) -> PathBuf {
+        match (cache_dir, shared) {
+            (Some(d), _) => PathBuf::from(d),
+            (None, true) => user_cache_dir().unwrap_or_else(out_dir),
+            (None, false) => out_dir(),
+        }
+    }
+
+    /// OUT_DIR of the running build script, or a temp dir when it is not called by cargo
+    pub fn out_dir() -> PathBuf {
+        if let Ok(d) = std::env::var("OUT_DIR") {
+            PathBuf::from(d)
+        } else {
+            std::env::temp_dir().join("arduino-sdk-sys")
+        }
+    }
+
+    /// per-user cache dir shared by all crates: `$XDG_CACHE_HOME`, `%LOCALAPPDATA%`,
+    /// `~/Library/Caches` or `~/.cache`
+    pub fn user_cache_dir() -> Option<PathBuf> {
+        let base = if let Ok(d) = std::env::var("XDG_CACHE_HOME") {
+            PathBuf::from(d)
+        } else if cfg!(windows) {
+            PathBuf::from(std::env::var("LOCALAPPDATA").ok()?)
+        } else if cfg!(target_os = "macos") {
+            Path::new(&std::env::var("HOME").ok()?).join("Library").join("Caches")
+        } else {
+            Path::new(&std::env::var("HOME").ok()?).join(".cache")
+        };
+        Some(base.join("arduino-sdk-sys"))
+    }
+
+    /// `p` joined to the current dir unless it is already absolute, `..` are kept. stands in for
+    /// `std::path::absolute`, which is newer than the toolchains the crate builds with
+    pub fn absolute(p: &Path) -> std::io::Result<PathBuf> {
+        if p.is_absolute() {
+            Ok(p.to_path_buf())
+        } else {
+            Ok(std::env::current_dir()?.join(p))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum LibraryLocation {
+        /// `<directories.user>/libraries`, the sketchbook
+        User,
+        /// `<runtime.platform.path>/libraries`
+        Platform,
+    }
+
+    /// source layout of a library, see the Arduino library specification
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LibraryLayout {
+        /// 1.0 format: sources in the root and in `utility/`, neither is searched recursively
+        Flat,
+        /// 1.5 format: sources anywhere below `src/`
+        Recursive,
+    }
+
+    /// a library folder of the sketchbook or of the platform
+    #[derive(Debug, Clone)]
+    pub struct Library {
+        /// folder name
+        pub name: String,
+        pub root: PathBuf,
+        /// `src/` of a 1.5 format library, otherwise the root
+        pub src_dir: PathBuf,
+        pub layout: LibraryLayout,
+        pub location: LibraryLocation,
+        /// library.properties, empty if there is none
+        pub properties: KVMap,
+    }
+
+    impl Library {
+        pub fn new(root: PathBuf, location: LibraryLocation) -> Self {
+            let src_dir = root.join("src");
+            let (src_dir, layout) = if src_dir.is_dir() {
+                (src_dir, LibraryLayout::Recursive)
+            } else {
+                (root.to_owned(), LibraryLayout::Flat)
+            };
+            let properties = std::fs::read_to_string(root.join("library.properties"))
+                .map(|s| parse_properties(&s))
+                .unwrap_or_default();
+            Self {
+                name: root.file_name().unwrap().to_string_lossy().to_string(),
+                root,
+                src_dir,
+                layout,
+                location,
+                properties,
+            }
+        }
+
+        pub fn get_property(&self, key: &str) -> Option<String> {
+            self.properties.get(key).cloned()
+        }
+
+        /// the folder name, the `name` of library.properties, or that name with spaces replaced
+        /// by `_` as arduino-cli names the folder on install
+        pub fn matches_name(&self, name: &str) -> bool {
+            let name = name.trim();
+            self.name == name
+                || self.get_property("name").as_deref() == Some(name)
+                || self.name == name.replace(' ', "_")
+        }
+
+        /// `architectures` of library.properties contains `*` or `arch`. no property means any
+        pub fn supports_arch(&self, arch: &str) -> bool {
+            match self.get_property("architectures") {
+                Some(a) => a.split(',').map(|s| s.trim()).any(|s| s == "*" || s == arch),
+                None => true,
+            }
+        }
+
+        /// library names of `depends`, without their version constraints
+        pub fn depends(&self) -> Vec<String> {
+            self.get_property("depends")
+                .unwrap_or_default()
+                .split(',')
+                .map(|d| d.split('(').next().unwrap_or_default().trim().to_string())
+                .filter(|d| d.len() > 0)
+                .collect()
+        }
+
+        /// `dot_a_linkage=true`: link it as an archive, only the objects that resolve a symbol
+        /// are pulled in. otherwise every object is linked, like the Arduino builder does.
+        pub fn dot_a_linkage(&self) -> bool {
+            self.get_property("dot_a_linkage").as_deref() == Some("true")
+        }
+
+        /// `ldflags` of library.properties
+        pub fn ldflags(&self) -> Vec<String> {
+            split_quoted_string(&self.get_property("ldflags").unwrap_or_default())
+        }
+
+        /// the source dir, and `utility/` of a 1.0 format library
+        pub fn include_dirs(&self) -> Vec<PathBuf> {
+            let mut result = vec![self.src_dir.to_owned()];
+            let utility = self.root.join("utility");
+            if self.layout == LibraryLayout::Flat && utility.is_dir() {
+                result.push(utility);
+            }
+            result
+        }
+
+        /// whether `#include <header>` names a file of this library. if library.properties
+        /// declares `includes`, only those headers count.
+        pub fn provides(&self, header: &str) -> bool {
+            if let Some(includes) = self.get_property("includes") {
+                return includes.split(',').any(|i| i.trim() == header);
+            }
+            self.src_dir.join(header).is_file()
+        }
+
+        /// source files with extension `ext` (no dot) to compile for `arch`.
+        /// a 1.0 format library has them in the root and `utility/` only, so `examples/` and
+        /// `extras/` are left out. a 1.5 format library has them anywhere below `src/`, except in
+        /// `src/<other arch>/` folders named after another entry of `architectures`.
+        pub fn source_files(&self, ext: &str, arch: &str) -> Vec<PathBuf> {
+            let glob_files = |dir: &Path, pattern: &str| {
+                let pat = format!("{}/{}", glob::Pattern::escape(&dir.to_string_lossy()), pattern);
+                glob::glob(&pat)
+                    .map(|g| g.filter_map(Result::ok).collect::<Vec<_>>())
+                    .unwrap_or_default()
+            };
+
+            let mut result = match self.layout {
+                LibraryLayout::Flat => {
+                    let mut r = glob_files(&self.root, &format!("*.{}", ext));
+                    r.extend(glob_files(&self.root.join("utility"), &format!("*.{}", ext)));
+                    r
+                }
+                LibraryLayout::Recursive => {
+                    let other_archs = self
+                        .get_property("architectures")
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|a| a.trim().to_string())
+                        .filter(|a| a != arch && a != "*" && a.len() > 0)
+                        .collect::<Vec<_>>();
+                    glob_files(&self.src_dir, &format!("**/*.{}", ext))
+                        .into_iter()
+                        .filter(|f| {
+                            let top = f
+                                .strip_prefix(&self.src_dir)
+                                .ok()
+                                .and_then(|r| r.components().next())
+                                .map(|c| c.as_os_str().to_string_lossy().to_string());
+                            // a file directly in src/ is never arch specific
+                            let in_subdir = f.parent() != Some(self.src_dir.as_path());
+                            !(in_subdir && top.is_some_and(|t| other_archs.contains(&t)))
+                        })
+                        .collect()
+                }
+            };
+            result.sort();
+            result
+        }
 
-            let arch_dir = manifest_dir.join(&relative_p);
+        /// headers and sources, the files whose `#include` lines are scanned
+        fn scan_files(&self, arch: &str) -> Vec<PathBuf> {
+            ["h", "hpp", "c", "cpp", "S"]
+                .iter()
+                .flat_map(|e| self.source_files(e, arch))
+                .collect()
+        }
+    }
 
-            arch_dir
+    /// the headers named by `#include <...>` and `#include "..."` lines
+    fn scan_includes(content: &str) -> Vec<String> {
+        let mut result = vec![];
+        for l in content.lines() {
+            let l = match l.trim_start().strip_prefix('#') {
+                Some(l) => l.trim_start(),
+                None => continue,
+            };
+            let l = match l.strip_prefix("include") {
+                Some(l) => l.trim_start(),
+                None => continue,
+            };
+            let close = match l.chars().next() {
+                Some('<') => '>',
+                Some('"') => '"',
+                _ => continue,
+            };
+            if let Some(end) = l[1..].find(close) {
+                result.push(l[1..1 + end].trim().to_string());
+            }
         }
+        result
+    }
+
+    /// the library named `name`, the sketchbook shadows the platform, and a library that
+    /// supports `arch` is preferred
+    fn find_library<'a>(installed: &'a [Library], name: &str, arch: &str) -> Option<&'a Library> {
+        installed
+            .iter()
+            .filter(|l| l.matches_name(name))
+            .min_by_key(|l| (!l.supports_arch(arch), l.location))
+    }
+
+    /// the library that provides `header`, with the priority rules of the Arduino builder:
+    /// libraries supporting `arch` first, then the closest match between library name and header
+    /// name (exact, `<name>-master`, prefix, suffix, substring), then sketchbook before platform.
+    fn best_library_for<'a>(installed: &'a [Library], header: &str, arch: &str) -> Option<&'a Library> {
+        let stem = Path::new(header)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name_priority = |l: &Library| {
+            let n = l.name.as_str();
+            if n == stem {
+                0
+            } else if n == format!("{}-master", stem) {
+                1
+            } else if n.starts_with(&stem) {
+                2
+            } else if n.ends_with(&stem) {
+                3
+            } else if n.contains(&stem) {
+                4
+            } else {
+                5
+            }
+        };
+        installed
+            .iter()
+            .filter(|l| l.provides(header))
+            .min_by_key(|l| (!l.supports_arch(arch), name_priority(l), l.location))
+    }
+
+    /// `key=value` lines of a .properties file, `#` starts a comment line
+    fn parse_properties(content: &str) -> KVMap {
+        content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect()
     }
 
     fn get_dir_entries<P: AsRef<Path>>(
@@ -1160,7 +4087,7 @@ mod arduino_cli_util {
     }
 
     ///get directories.user from arduino-cli.yaml config file
-    fn get_user() -> Option<String> {
+    pub fn get_user() -> Option<String> {
         if let Ok(output) = Command::new("arduino-cli")
             .arg("config")
             .arg("dump")
@@ -1192,8 +4119,8 @@ mod arduino_cli_util {
             .arg("--format")
             .arg("yaml")
             .output();
-        if let Err(_) = output {
-            println!("failed to execute process");
+        if let Err(e) = &output {
+            eprintln!("failed to execute arduino-cli: {}", e);
             return None;
         }
         let output = output.unwrap();
@@ -1215,8 +4142,46 @@ mod arduino_cli_util {
         None
     }
 
+    /// replace the `{key}` placeholders of `s` by `lookup`, also those of the replacements, like
+    /// the arduino builder. unknown placeholders are kept.
+    pub fn expand_placeholders(s: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+        let mut s = s.to_string();
+        // a property may refer to itself, give up after a few rounds
+        for _ in 0..10 {
+            let mut out = String::new();
+            let mut rest = s.as_str();
+            let mut changed = false;
+            while let Some(start) = rest.find('{') {
+                out.push_str(&rest[..start]);
+                let after = &rest[start + 1..];
+                let value = after
+                    .find('}')
+                    .map(|end| &after[..end])
+                    .filter(|key| key.contains('{') == false)
+                    .and_then(|key| Some((key.len(), lookup(key)?)));
+                match value {
+                    Some((len, v)) => {
+                        out.push_str(&v);
+                        rest = &after[len + 1..];
+                        changed = true;
+                    }
+                    None => {
+                        out.push('{');
+                        rest = after;
+                    }
+                }
+            }
+            out.push_str(rest);
+            s = out;
+            if changed == false {
+                break;
+            }
+        }
+        s
+    }
+
     /// it like split_whitespace, but it enhanced to deal with quoted string
-    fn split_quoted_string(input: &str) -> Vec<String> {
+    pub fn split_quoted_string(input: &str) -> Vec<String> {
         let mut result = Vec::new();
         let mut current_item = String::new();
         let mut inside_quotes = false;
@@ -1306,13 +4271,289 @@ mod arduino_cli_util {
     }
 
     #[cfg(test)]
-    mod tests {
-        use super::Info;
+    pub(crate) mod tests {
+        use super::*;
         #[test]
         fn it_works() {
             let info = Info::new().unwrap();
             println!("{:#?}", info.default_archive_dir());
         }
+        /// an Info on a fake sketchbook `<root>/user` and platform [`test_platform`]
+        pub(crate) fn test_info(root: &Path, yaml: &str) -> Info {
+            let cfg = root.join("custom.yaml");
+            std::fs::write(&cfg, yaml).unwrap();
+            let platform = test_platform(root);
+            let mut orig_properties = KVMap::new();
+            for (k, v) in [
+                ("runtime.platform.path", platform.to_owned()),
+                ("build.core.path", platform.join("cores").join("arduino")),
+                ("build.variant.path", platform.join("variants").join("standard")),
+            ] {
+                std::fs::create_dir_all(&v).unwrap();
+                orig_properties.insert(k.to_string(), v.to_string_lossy().to_string());
+            }
+            for (k, v) in [("version", "1.0.0"), ("build.core", "arduino"), ("build.variant", "standard")] {
+                orig_properties.insert(k.to_string(), v.to_string());
+            }
+            let mut info = Info {
+                orig_properties,
+                downstream_config: DownStreamConfig::new(Some(cfg.to_str().unwrap())),
+                pats: HashMap::new(),
+                user: root.join("user").to_string_lossy().to_string(),
+                libraries: vec![],
+                warnings: vec![],
+            };
+            (info.libraries, info.warnings) = info.resolve_libraries();
+            info
+        }
+
+        /// the platform of [`test_info`], laid out like an arduino-cli data dir
+        pub(crate) fn test_platform(root: &Path) -> PathBuf {
+            root.join("data/packages/arduino/hardware/avr/1.0.0")
+        }
+
+        /// host gcc recipes, so the platform of a [`test_info`] really compiles. the
+        /// "compile_flags" of the down-stream config are merged like for a real platform.
+        pub(crate) fn use_host_recipes(info: &mut Info) {
+            use_recipes(
+                info,
+                &[
+                    ("recipe.S.o.pattern", "gcc -Os"),
+                    ("recipe.c.o.pattern", "gcc -Os"),
+                    ("recipe.cpp.o.pattern", "g++ -Os"),
+                    ("recipe.ar.pattern", "ar"),
+                ],
+            );
+        }
+
+        /// set the (recipe key, pattern) properties and parse the recipes again
+        pub(crate) fn use_recipes(info: &mut Info, recipes: &[(&str, &str)]) {
+            for (key, pattern) in recipes {
+                info.orig_properties.insert(key.to_string(), pattern.to_string());
+            }
+            info.pats = get_patterns_(&info.orig_properties, &info.downstream_config);
+        }
+
+        pub(crate) fn write_file(path: &Path, content: &str) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        #[test]
+        fn cache_root_follows_the_location_rules() {
+            let user = || Some(PathBuf::from("/home/u/.cache/arduino-sdk-sys"));
+            let out = || PathBuf::from("/target/debug/build/x/out");
+            let env = Some("/ci/cache".to_string());
+            assert_eq!(select_cache_root(env.clone(), false, user, out), Path::new("/ci/cache"));
+            assert_eq!(select_cache_root(env, true, user, out), Path::new("/ci/cache"));
+            assert_eq!(select_cache_root(None, true, user, out), Path::new("/home/u/.cache/arduino-sdk-sys"));
+            assert_eq!(select_cache_root(None, true, || None, out), out());
+            assert_eq!(select_cache_root(None, false, user, out), out());
+            // outside of a build script the private cache is a temp dir, never the crate dir
+            if std::env::var("OUT_DIR").is_err() {
+                assert!(out_dir().starts_with(env!("CARGO_MANIFEST_DIR")) == false);
+                assert!(out_dir().starts_with(std::env::temp_dir()));
+            }
+        }
+
+        #[test]
+        fn expand_placeholders_works() {
+            let props = HashMap::from([
+                ("build.path", "/out"),
+                ("tools.bossac.path", "{runtime.tools.bossac.path}"),
+                ("runtime.tools.bossac.path", "/tools/bossac"),
+                ("self", "{self}"),
+            ]);
+            let lookup = |k: &str| props.get(k).map(|v| v.to_string());
+            assert_eq!(
+                expand_placeholders("\"{tools.bossac.path}/bossac\" {build.path}/{x} {self} {", lookup),
+                "\"/tools/bossac/bossac\" /out/{x} {self} {"
+            );
+        }
+
+        #[test]
+        fn scan_includes_works() {
+            let src = "#include <Wire.h>\n  #  include \"utility/twi.h\" // twi\n#define X 1\n// #include <no.h\n#include_next <x.h>\n";
+            assert_eq!(scan_includes(src), ["Wire.h", "utility/twi.h"]);
+        }
+
+        #[test]
+        fn resolve_libraries_follows_includes() {
+            let root = tempfile::tempdir().unwrap();
+            let (user, platform) = (root.path().join("user"), test_platform(root.path()));
+            write_file(&platform.join("cores/arduino/Arduino.h"), "");
+            write_file(
+                &user.join("libraries/LiquidCrystal_I2C/LiquidCrystal_I2C.cpp"),
+                "#include <Arduino.h>\n#include <Wire.h>\n#include <avr/io.h>\n",
+            );
+            write_file(&user.join("libraries/LiquidCrystal_I2C/LiquidCrystal_I2C.h"), "");
+            // the platform Wire is shadowed by a sketchbook Wire
+            write_file(&platform.join("libraries/Wire/src/Wire.h"), "");
+            write_file(&user.join("libraries/Wire/src/Wire.h"), "#include \"utility/twi.h\"");
+            write_file(&user.join("libraries/Wire/src/utility/twi.h"), "");
+            // never included
+            write_file(&platform.join("libraries/SPI/src/SPI.h"), "");
+            // provides Wire.h too, but its name is a worse match
+            write_file(&user.join("libraries/MyWire/src/Wire.h"), "");
+
+            let info = test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nexternal_libraries: [LiquidCrystal_I2C, Missing]\n",
+            );
+            let libs = info
+                .libraries()
+                .iter()
+                .map(|l| (l.name.as_str(), l.location))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                libs,
+                [
+                    ("LiquidCrystal_I2C", LibraryLocation::User),
+                    ("Wire", LibraryLocation::User)
+                ]
+            );
+        }
+
+        #[test]
+        fn library_properties_are_honored() {
+            let root = tempfile::tempdir().unwrap();
+            let libs = root.path().join("user/libraries");
+            write_file(
+                &libs.join("Adafruit_SSD1306/library.properties"),
+                "name=Adafruit SSD1306\ndepends=Adafruit GFX Library (>=1.1.0)\narchitectures=*\n",
+            );
+            write_file(&libs.join("Adafruit_SSD1306/src/Adafruit_SSD1306.h"), "#include <Other.h>");
+            // reached through `depends` only
+            write_file(
+                &libs.join("Adafruit_GFX_Library/library.properties"),
+                "name=Adafruit GFX Library\nincludes=Adafruit_GFX.h\n",
+            );
+            write_file(&libs.join("Adafruit_GFX_Library/src/Adafruit_GFX.h"), "");
+            write_file(&libs.join("Adafruit_GFX_Library/src/Other.h"), "");
+            // Other.h: a better name match, but for another architecture
+            write_file(&libs.join("Other/library.properties"), "architectures=samd,esp32\n");
+            write_file(&libs.join("Other/src/Other.h"), "");
+            write_file(&libs.join("Other2/src/Other.h"), "");
+
+            let info = test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nexternal_libraries: [Adafruit SSD1306, Missing]\n",
+            );
+            let names = info.libraries().iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, ["Adafruit_SSD1306", "Adafruit_GFX_Library", "Other2"]);
+            assert!(!info.libraries()[1].provides("Other.h"));
+            // kept for the build script to print, not printed while resolving
+            assert_eq!(
+                info.warnings(),
+                ["external library 'Missing' not found in the sketchbook or the platform libraries"]
+            );
+        }
+
+        #[test]
+        fn precompiled_dir_prefers_fpu_variant() {
+            let root = tempfile::tempdir().unwrap();
+            let lib = root.path().join("user/libraries/BSEC");
+            write_file(&lib.join("library.properties"), "precompiled=full\nldflags=-lalgobsec\n");
+            write_file(&lib.join("src/cortex-m4/libalgobsec.a"), "");
+            write_file(&lib.join("src/cortex-m4/fpv4-sp-d16-hard/libalgobsec.a"), "");
+
+            let mut info = test_info(root.path(), "fqbn: arduino:samd:x\nexternal_libraries: [BSEC]\n");
+            info.orig_properties.insert("build.mcu".into(), "cortex-m4".into());
+            let l = info.libraries()[0].clone();
+            assert_eq!(info.precompiled_dir(&l), Some(lib.join("src/cortex-m4")));
+            assert!(!info.compile_sources_of(&l));
+            assert_eq!(l.ldflags(), ["-lalgobsec"]);
+
+            info.orig_properties.insert("build.fpu".into(), "fpv4-sp-d16".into());
+            info.orig_properties.insert("build.float-abi".into(), "hard".into());
+            assert_eq!(
+                info.precompiled_dir(&l),
+                Some(lib.join("src/cortex-m4/fpv4-sp-d16-hard"))
+            );
+
+            info.orig_properties.insert("build.mcu".into(), "cortex-m0plus".into());
+            assert!(info.compile_sources_of(&l));
+        }
+
+        #[test]
+        fn library_layouts_pick_their_sources() {
+            let root = tempfile::tempdir().unwrap();
+            let flat = root.path().join("Flat");
+            for f in ["Flat.h", "Flat.cpp", "utility/twi.c", "utility/deep/no.c", "examples/Demo/Demo.cpp", "extras/t.cpp"] {
+                write_file(&flat.join(f), "");
+            }
+            let l = Library::new(flat.to_owned(), LibraryLocation::User);
+            assert_eq!(l.layout, LibraryLayout::Flat);
+            assert_eq!(l.include_dirs(), [flat.to_owned(), flat.join("utility")]);
+            assert_eq!(l.source_files("cpp", "avr"), [flat.join("Flat.cpp")]);
+            assert_eq!(l.source_files("c", "avr"), [flat.join("utility/twi.c")]);
+
+            let servo = root.path().join("Servo");
+            write_file(&servo.join("library.properties"), "architectures=avr,sam,samd\n");
+            for f in ["src/Servo.h", "src/avr/Servo.cpp", "src/sam/Servo.cpp", "src/samd/Servo.cpp", "src/common/x.cpp", "examples/Sweep/Sweep.cpp"] {
+                write_file(&servo.join(f), "");
+            }
+            let l = Library::new(servo.to_owned(), LibraryLocation::Platform);
+            assert_eq!(l.layout, LibraryLayout::Recursive);
+            assert_eq!(l.include_dirs(), [servo.join("src")]);
+            assert_eq!(
+                l.source_files("cpp", "avr"),
+                [servo.join("src/avr/Servo.cpp"), servo.join("src/common/x.cpp")]
+            );
+        }
+
+        #[test]
+        fn sketch_sources_are_relative_to_the_config() {
+            let root = tempfile::tempdir().unwrap();
+            write_file(&root.path().join("csrc/lcd_shim.cpp"), "#include \"lcd_shim.h\"");
+            write_file(&root.path().join("csrc/lcd_shim.h"), "#include <Wire.h>");
+            write_file(&root.path().join("csrc/sub/timer.c"), "");
+            write_file(&root.path().join("csrc/README.md"), "");
+            write_file(&test_platform(root.path()).join("libraries/Wire/src/Wire.h"), "");
+
+            let info = test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nsources: [csrc/**/*]\n",
+            );
+            assert_eq!(
+                info.get_sketch_sources(),
+                [root.path().join("csrc/lcd_shim.cpp"), root.path().join("csrc/sub/timer.c")]
+            );
+            // Wire is only reachable from the shim header
+            assert_eq!(info.libraries()[0].name, "Wire");
+            assert!(info.config_inputs().contains(&root.path().join("csrc")));
+        }
+
+        #[test]
+        fn sketch_sources_are_relative_to_the_crate() {
+            let root = tempfile::tempdir().unwrap();
+            write_file(&root.path().join("Cargo.toml"), "[package]\n");
+            write_file(&root.path().join("csrc/timer.c"), "");
+            // the config is not in the crate root
+            std::fs::create_dir_all(root.path().join("config")).unwrap();
+            let info = test_info(&root.path().join("config"), "fqbn: arduino:avr:uno\nsources: [csrc/*.c, shims/*.cpp]\n");
+
+            assert_eq!(info.downstream_config.crate_dir(), Some(root.path().to_path_buf()));
+            assert_eq!(info.get_sketch_sources(), [root.path().join("csrc/timer.c")]);
+            assert_eq!(info.unmatched_sources_patterns(), [root.path().join("shims/*.cpp")]);
+            assert!(DownStreamConfig::new(None).get_sources_patterns().is_empty());
+        }
+
+        #[test]
+        fn libraries_follow_link_order() {
+            let root = tempfile::tempdir().unwrap();
+            for l in ["Servo", "Wire2", "LiquidCrystal_I2C"] {
+                write_file(&root.path().join("user/libraries").join(l).join("src").join("a.h"), "");
+            }
+
+            let info = test_info(
+                root.path(),
+                "fqbn: arduino:avr:uno\nexternal_libraries: [LiquidCrystal_I2C, Servo, Wire2]\nlink_order: [Wire2]\n",
+            );
+            let names = info.libraries().iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, ["Wire2", "LiquidCrystal_I2C", "Servo"]);
+            assert!(info.libraries()[2].src_dir.ends_with("Servo/src"));
+        }
     }
 }
 
@@ -1320,7 +4561,7 @@ mod arduino_cli_util {
 mod tests {
     #[test]
     fn it_works() {
-        let fqbn = "arduino:avr:diecimila:cpu=atmega168";
+        let _fqbn = "arduino:avr:diecimila:cpu=atmega168";
         // let fqbn = "arduino:esp32:nano_nora:USBMode=hwcdc";
     }
 }
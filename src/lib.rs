@@ -1,6 +1,8 @@
 #![no_std]
 
 
+// `bindings` is empty without the native_bindgen feature
+#[allow(unused_imports)]
 pub use bindings::*;
 
     #[cfg(all(target_arch = "avr", feature = "native_bindgen"))]